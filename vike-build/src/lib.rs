@@ -0,0 +1,103 @@
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Writes `$OUT_DIR/<name>.rs`, a table of include_bytes! entries for every file
+// under `dir`. Load it with `vike::include_assets!("<name>")`
+pub fn embed_dir(dir: impl AsRef<Path>, name: &str) -> io::Result<()> {
+    let dir = dir.as_ref();
+    let out_dir = std::env::var_os("OUT_DIR")
+        .ok_or_else(|| io::Error::other("OUT_DIR is not set, call embed_dir from build.rs"))?;
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    std::fs::write(
+        Path::new(&out_dir).join(format!("{}.rs", name)),
+        table(dir)?,
+    )
+}
+
+fn table(dir: &Path) -> io::Result<String> {
+    let root = std::fs::canonicalize(dir)?;
+    let mut files = Vec::new();
+    collect(&root, &mut files)?;
+    files.sort();
+
+    let mut table = String::from("&[\n");
+    for file in files {
+        let relative = file
+            .strip_prefix(&root)
+            .unwrap()
+            .components()
+            .map(|component| component.as_os_str().to_str())
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| io::Error::other(format!("{:?} is not valid UTF-8", file)))?
+            .join("/");
+        let absolute = file
+            .to_str()
+            .ok_or_else(|| io::Error::other(format!("{:?} is not valid UTF-8", file)))?;
+        writeln!(
+            table,
+            "    ({:?}, include_bytes!({:?}) as &[u8]),",
+            relative, absolute
+        )
+        .unwrap();
+    }
+    table.push(']');
+    Ok(table)
+}
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_lists_nested_files_with_relative_keys() {
+        let dir = std::env::temp_dir().join(format!("vike-build-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("models/trees")).unwrap();
+        std::fs::create_dir_all(dir.join("textures")).unwrap();
+        std::fs::write(dir.join("models/trees/bark.obj"), "o bark").unwrap();
+        std::fs::write(dir.join("models/trees/bark.mtl"), "newmtl bark").unwrap();
+        std::fs::write(dir.join("textures/bark.png"), [0u8; 4]).unwrap();
+
+        let table = table(&dir).unwrap();
+        let root = std::fs::canonicalize(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let keys = table
+            .lines()
+            .filter_map(|line| line.split('"').nth(1))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                "models/trees/bark.mtl",
+                "models/trees/bark.obj",
+                "textures/bark.png"
+            ]
+        );
+        assert!(table.starts_with("&[\n") && table.ends_with(']'));
+        let include = format!(
+            "include_bytes!({:?}) as &[u8]",
+            root.join("textures").join("bark.png").to_str().unwrap()
+        );
+        assert!(table.contains(&include), "{}", table);
+    }
+
+    #[test]
+    fn table_fails_for_a_missing_directory() {
+        assert!(table(Path::new("does/not/exist")).is_err());
+    }
+}
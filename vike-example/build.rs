@@ -12,5 +12,9 @@ fn main() -> Result<()> {
     let paths_to_copy = vec!["models/"];
     copy_items(&paths_to_copy, out_dir, &copy_options)?;
 
+    if env::var_os("CARGO_FEATURE_EMBED").is_some() {
+        vike_build::embed_dir("models", "models")?;
+    }
+
     Ok(())
 }
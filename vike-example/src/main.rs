@@ -5,9 +5,11 @@ use image::{ImageBuffer, Rgba};
 use vike::{
     HeadlessVike,
     camera::CameraController,
-    game_object::{EulerRot, GameObjectStore, Quat, Transform3D, Vec3},
+    debug::Debug,
+    game_object::{EulerRot, GameObjectStore, LightKind, Quat, Transform3D, Vec3},
     renderer::Renderer,
     run_headless,
+    stats::FrameStats,
 };
 use winit::{
     event::{DeviceEvent, MouseButton, WindowEvent},
@@ -28,6 +30,13 @@ impl HeadlessVike for Vike {
         camera_controller.sensitivity = 0.4;
         camera_controller.speed = 6.0;
 
+        #[cfg(feature = "embed")]
+        let assets = vike::assets::MemorySource::embedded(vike::include_assets!("models")).unwrap();
+        // build.rs copies the models next to the build output
+        #[cfg(not(feature = "embed"))]
+        let assets = vike::assets::FileSource::new(concat!(env!("OUT_DIR"), "/models"));
+        game_objects.set_asset_source(assets);
+
         let cube_model = game_objects.load_model("cube.obj", renderer).await.unwrap();
 
         game_objects.new_game_object(
@@ -88,6 +97,7 @@ impl HeadlessVike for Vike {
             Some(cube_model.clone()),
             Vec3::new(1.0, 0.0, 0.0),
             1000.0,
+            LightKind::Point,
         );
         game_objects.new_array(
             "red",
@@ -113,6 +123,7 @@ impl HeadlessVike for Vike {
             Some(cube_model.clone()),
             Vec3::new(0.0, 1.0, 0.0),
             1000.0,
+            LightKind::Point,
         );
         game_objects.new_array(
             "green",
@@ -138,6 +149,7 @@ impl HeadlessVike for Vike {
             Some(cube_model.clone()),
             Vec3::new(0.0, 0.0, 1.0),
             1000.0,
+            LightKind::Point,
         );
         game_objects.new_array(
             "blue",
@@ -154,19 +166,19 @@ impl HeadlessVike for Vike {
         &mut self,
         game_objects: &mut GameObjectStore,
         _camera_controller: &mut CameraController,
+        _debug: &mut Debug,
         dt: Duration,
     ) {
         let dt_secs = dt.as_secs_f32();
 
-        println!("dt: {:?}", dt);
-
         for (_, light) in game_objects.lights_mut() {
             light.transform.position =
                 Quat::from_axis_angle(Vec3::Y, dt_secs * 0.5) * light.transform.position;
         }
     }
 
-    async fn frame(&mut self, image_buffer: ImageBuffer<Rgba<u8>, Vec<u8>>) {
+    async fn frame(&mut self, image_buffer: ImageBuffer<Rgba<u8>, Vec<u8>>, stats: &FrameStats) {
+        println!("{}", stats);
         println!("saving image");
         image_buffer.save("image.png").unwrap();
         unimplemented!()
@@ -192,6 +204,12 @@ fn _window_event(
                         window.set_cursor_visible(true);
                         window.set_cursor_grab(CursorGrabMode::None).unwrap_or(());
                     }
+                    KeyCode::F3 if event.state.is_pressed() => {
+                        renderer.set_stats_overlay(!renderer.stats_overlay());
+                    }
+                    KeyCode::F4 if event.state.is_pressed() => {
+                        println!("{}", renderer.stats());
+                    }
                     _ => camera_controller.process_keyboard(code, event.state),
                 }
                 camera_controller.process_keyboard(code, event.state);
@@ -0,0 +1,111 @@
+struct Bloom {
+    threshold: f32,
+    knee: f32,
+    radius: f32,
+}
+
+struct VertexOutput {
+    @location(0) uv: vec2<f32>,
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+
+    out.uv = vec2<f32>(
+        f32((vi << 1u) & 2u),
+        f32(vi & 2u),
+    );
+    out.clip_position = vec4<f32>(out.uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv.y = 1.0 - out.uv.y;
+    return out;
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> bloom: Bloom;
+
+fn sample_source(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(source, source_sampler, uv, 0.0).rgb;
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+fn karis_average(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>, d: vec3<f32>) -> vec3<f32> {
+    let weights = 1.0 / (1.0 + vec4(luminance(a), luminance(b), luminance(c), luminance(d)));
+    return (a * weights.x + b * weights.y + c * weights.z + d * weights.w) / dot(weights, vec4(1.0));
+}
+
+fn soft_threshold(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    var soft = clamp(brightness - bloom.threshold + bloom.knee, 0.0, 2.0 * bloom.knee);
+    soft = soft * soft / (4.0 * bloom.knee + 0.00001);
+    return color * max(soft, brightness - bloom.threshold) / max(brightness, 0.00001);
+}
+
+fn downsample(uv: vec2<f32>, karis: bool) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+
+    let a = sample_source(uv + texel * vec2(-2.0, 2.0));
+    let b = sample_source(uv + texel * vec2(0.0, 2.0));
+    let c = sample_source(uv + texel * vec2(2.0, 2.0));
+    let d = sample_source(uv + texel * vec2(-2.0, 0.0));
+    let e = sample_source(uv);
+    let f = sample_source(uv + texel * vec2(2.0, 0.0));
+    let g = sample_source(uv + texel * vec2(-2.0, -2.0));
+    let h = sample_source(uv + texel * vec2(0.0, -2.0));
+    let i = sample_source(uv + texel * vec2(2.0, -2.0));
+    let j = sample_source(uv + texel * vec2(-1.0, 1.0));
+    let k = sample_source(uv + texel * vec2(1.0, 1.0));
+    let l = sample_source(uv + texel * vec2(-1.0, -1.0));
+    let m = sample_source(uv + texel * vec2(1.0, -1.0));
+
+    if karis {
+        return karis_average(j, k, l, m) * 0.5
+            + karis_average(a, b, d, e) * 0.125
+            + karis_average(b, c, e, f) * 0.125
+            + karis_average(d, e, g, h) * 0.125
+            + karis_average(e, f, h, i) * 0.125;
+    }
+
+    return e * 0.125
+        + (a + c + g + i) * 0.03125
+        + (b + d + f + h) * 0.0625
+        + (j + k + l + m) * 0.125;
+}
+
+@fragment
+fn fs_downsample_first(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4(soft_threshold(downsample(in.uv, true)), 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4(downsample(in.uv, false), 1.0);
+}
+
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let r = bloom.radius;
+
+    let a = sample_source(in.uv + vec2(-r, r));
+    let b = sample_source(in.uv + vec2(0.0, r));
+    let c = sample_source(in.uv + vec2(r, r));
+    let d = sample_source(in.uv + vec2(-r, 0.0));
+    let e = sample_source(in.uv);
+    let f = sample_source(in.uv + vec2(r, 0.0));
+    let g = sample_source(in.uv + vec2(-r, -r));
+    let h = sample_source(in.uv + vec2(0.0, -r));
+    let i = sample_source(in.uv + vec2(r, -r));
+
+    let color = e * 4.0 + (b + d + f + h) * 2.0 + (a + c + g + i);
+    return vec4(color / 16.0, 1.0);
+}
@@ -0,0 +1,69 @@
+const PI: f32 = 3.1415926535897932384626433832795;
+
+struct Face {
+    index: u32,
+}
+
+@group(0) @binding(0)
+var equirect: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> face: Face;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+
+    out.uv = vec2<f32>(
+        f32((vi << 1u) & 2u),
+        f32(vi & 2u),
+    );
+    out.clip_position = vec4<f32>(out.uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv.y = 1.0 - out.uv.y;
+    return out;
+}
+
+fn face_direction(index: u32, uv: vec2<f32>) -> vec3<f32> {
+    let st = uv * 2.0 - 1.0;
+    switch index {
+        case 0u: { return vec3(1.0, -st.y, -st.x); }
+        case 1u: { return vec3(-1.0, -st.y, st.x); }
+        case 2u: { return vec3(st.x, 1.0, st.y); }
+        case 3u: { return vec3(st.x, -1.0, -st.y); }
+        case 4u: { return vec3(st.x, -st.y, 1.0); }
+        default: { return vec3(-st.x, -st.y, -1.0); }
+    }
+}
+
+// Rgba32Float is not filterable everywhere, so filter by hand
+fn load_bilinear(uv: vec2<f32>) -> vec3<f32> {
+    let size = vec2<i32>(textureDimensions(equirect));
+    let coords = uv * vec2<f32>(size) - 0.5;
+    let base = vec2<i32>(floor(coords));
+    let t = fract(coords);
+
+    let x0 = (base.x % size.x + size.x) % size.x;
+    let x1 = (x0 + 1) % size.x;
+    let y0 = clamp(base.y, 0, size.y - 1);
+    let y1 = clamp(base.y + 1, 0, size.y - 1);
+
+    let top = mix(textureLoad(equirect, vec2(x0, y0), 0), textureLoad(equirect, vec2(x1, y0), 0), t.x);
+    let bottom = mix(textureLoad(equirect, vec2(x0, y1), 0), textureLoad(equirect, vec2(x1, y1), 0), t.x);
+    return mix(top, bottom, t.y).rgb;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let dir = normalize(face_direction(face.index, in.uv));
+    let uv = vec2<f32>(
+        atan2(dir.z, dir.x) / (2.0 * PI) + 0.5,
+        acos(clamp(dir.y, -1.0, 1.0)) / PI,
+    );
+    return vec4<f32>(load_bilinear(uv), 1.0);
+}
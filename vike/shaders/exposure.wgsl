@@ -0,0 +1,72 @@
+struct Params {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    compensation: f32,
+    num_pixels: u32,
+}
+
+@group(0) @binding(0)
+var hdr_image: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> params: Params;
+@group(0) @binding(2)
+var<storage, read_write> histogram: array<atomic<u32>, 256>;
+@group(0) @binding(3)
+var<storage, read_write> state: array<f32, 2>;
+
+var<workgroup> bins: array<atomic<u32>, 256>;
+var<workgroup> weighted: array<f32, 256>;
+
+fn luminance_bin(color: vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+    if luminance < 0.0001 {
+        return 0u;
+    }
+
+    let t = clamp((log2(luminance) - params.min_log_luminance) / params.log_luminance_range, 0.0, 1.0);
+    return u32(t * 254.0 + 1.0);
+}
+
+@compute @workgroup_size(16, 16)
+fn build_histogram(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    atomicStore(&bins[index], 0u);
+    workgroupBarrier();
+
+    if all(id.xy < textureDimensions(hdr_image)) {
+        let color = textureLoad(hdr_image, id.xy, 0).rgb;
+        atomicAdd(&bins[luminance_bin(color)], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram[index], atomicLoad(&bins[index]));
+}
+
+@compute @workgroup_size(256)
+fn average_histogram(@builtin(local_invocation_index) index: u32) {
+    let count = atomicLoad(&histogram[index]);
+    weighted[index] = f32(count) * f32(index);
+    atomicStore(&histogram[index], 0u);
+    workgroupBarrier();
+
+    for (var stride = 128u; stride > 0u; stride = stride >> 1u) {
+        if index < stride {
+            weighted[index] = weighted[index] + weighted[index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if index == 0u {
+        let lit = max(f32(params.num_pixels) - f32(count), 1.0);
+        let average_bin = weighted[0] / lit - 1.0;
+        let log_luminance = average_bin / 254.0 * params.log_luminance_range + params.min_log_luminance;
+        let luminance = exp2(log_luminance);
+
+        let adapted = select(mix(state[1], luminance, params.adaptation), luminance, state[1] <= 0.0);
+        state[1] = adapted;
+        state[0] = exp2(params.compensation) * 0.18 / max(adapted, 0.0001);
+    }
+}
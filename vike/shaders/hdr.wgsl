@@ -15,6 +15,58 @@ fn aces_tone_map(hdr: vec3<f32>) -> vec3<f32> {
     return clamp(m2 * (a / b), vec3(0.0), vec3(1.0));
 }
 
+fn reinhard_tone_map(hdr: vec3<f32>, white_point: f32) -> vec3<f32> {
+    let white = white_point * white_point;
+    return clamp(hdr * (1.0 + hdr / white) / (1.0 + hdr), vec3(0.0), vec3(1.0));
+}
+
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+fn agx_tone_map(hdr: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104,
+    );
+    let outset = mat3x3(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116,
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    var v = clamp(log2(max(inset * hdr, vec3(1e-10))), vec3(min_ev), vec3(max_ev));
+    v = agx_contrast((v - min_ev) / (max_ev - min_ev));
+    return pow(clamp(outset * v, vec3(0.0), vec3(1.0)), vec3(2.2));
+}
+
+struct ToneMapping {
+    exposure: f32,
+    white_point: f32,
+    tonemapper: u32,
+}
+@group(0) @binding(2)
+var<uniform> tone_mapping: ToneMapping;
+
+const TONE_MAP_NONE: u32 = 0u;
+const TONE_MAP_REINHARD: u32 = 1u;
+const TONE_MAP_ACES: u32 = 2u;
+const TONE_MAP_AGX: u32 = 3u;
+
+fn tone_map(hdr: vec3<f32>) -> vec3<f32> {
+    switch tone_mapping.tonemapper {
+        case TONE_MAP_REINHARD: { return reinhard_tone_map(hdr, tone_mapping.white_point); }
+        case TONE_MAP_ACES: { return aces_tone_map(hdr); }
+        case TONE_MAP_AGX: { return agx_tone_map(hdr); }
+        default: { return clamp(hdr, vec3(0.0), vec3(1.0)); }
+    }
+}
+
 struct VertexOutput {
     @location(0) uv: vec2<f32>,
     @builtin(position) clip_position: vec4<f32>,
@@ -43,7 +95,7 @@ var hdr_sampler: sampler;
 @fragment
 fn fs_main(vs: VertexOutput) -> @location(0) vec4<f32> {
     let hdr = textureSample(hdr_image, hdr_sampler, vs.uv);
-    let sdr = aces_tone_map(hdr.rgb);
+    let sdr = tone_map(hdr.rgb * tone_mapping.exposure);
 
     return vec4(sdr, hdr.a);
 }
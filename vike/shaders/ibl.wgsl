@@ -0,0 +1,171 @@
+const PI: f32 = 3.14159265359;
+
+struct Params {
+    face: u32,
+    roughness: f32,
+    source_size: f32,
+    num_samples: u32,
+}
+
+@group(0) @binding(0)
+var t_source: texture_cube<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+
+    out.uv = vec2<f32>(
+        f32((vi << 1u) & 2u),
+        f32(vi & 2u),
+    );
+    out.clip_position = vec4<f32>(out.uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv.y = 1.0 - out.uv.y;
+    return out;
+}
+
+fn face_direction(index: u32, uv: vec2<f32>) -> vec3<f32> {
+    let st = uv * 2.0 - 1.0;
+    switch index {
+        case 0u: { return vec3(1.0, -st.y, -st.x); }
+        case 1u: { return vec3(-1.0, -st.y, st.x); }
+        case 2u: { return vec3(st.x, 1.0, st.y); }
+        case 3u: { return vec3(st.x, -1.0, -st.y); }
+        case 4u: { return vec3(st.x, -st.y, 1.0); }
+        default: { return vec3(-st.x, -st.y, -1.0); }
+    }
+}
+
+fn radical_inverse(bits_in: u32) -> f32 {
+    var bits = (bits_in << 16u) | (bits_in >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+fn hammersley(i: u32, n: u32) -> vec2<f32> {
+    return vec2(f32(i) / f32(n), radical_inverse(i));
+}
+
+fn tangent_to_world(v: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
+    let up = select(vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0), abs(n.z) < 0.999);
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    return tangent * v.x + bitangent * v.y + n * v.z;
+}
+
+fn importance_sample_ggx(xi: vec2<f32>, n: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return tangent_to_world(vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta), n);
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Sample a mip whose texel covers roughly the same solid angle as the sample,
+// which keeps bright spots in the source from turning into fireflies
+fn sample_lod(pdf: f32) -> f32 {
+    let sample_solid_angle = 1.0 / (f32(params.num_samples) * pdf + 0.0001);
+    let texel_solid_angle = 4.0 * PI / (6.0 * params.source_size * params.source_size);
+    return max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let dir = normalize(face_direction(params.face, in.uv));
+    return vec4(textureSampleLevel(t_source, s_source, dir, 0.0).rgb, 1.0);
+}
+
+@fragment
+fn fs_irradiance(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n = normalize(face_direction(params.face, in.uv));
+
+    var irradiance = vec3(0.0);
+    for (var i = 0u; i < params.num_samples; i++) {
+        let xi = hammersley(i, params.num_samples);
+        let phi = 2.0 * PI * xi.x;
+        let cos_theta = sqrt(1.0 - xi.y);
+        let sin_theta = sqrt(xi.y);
+        let l = tangent_to_world(vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta), n);
+        let lod = sample_lod(cos_theta / PI);
+        irradiance += textureSampleLevel(t_source, s_source, l, lod).rgb;
+    }
+
+    return vec4(irradiance / f32(params.num_samples), 1.0);
+}
+
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n = normalize(face_direction(params.face, in.uv));
+    let v = n;
+
+    var color = vec3(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < params.num_samples; i++) {
+        let h = importance_sample_ggx(hammersley(i, params.num_samples), n, params.roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = dot(n, l);
+        if n_dot_l > 0.0 {
+            let n_dot_h = max(dot(n, h), 0.0);
+            let pdf = distribution_ggx(n_dot_h, params.roughness) * 0.25;
+            let lod = sample_lod(pdf);
+            color += textureSampleLevel(t_source, s_source, l, lod).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+
+    return vec4(color / max(weight, 0.0001), 1.0);
+}
+
+fn geometry_smith_ibl(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness / 2.0;
+    let g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+@fragment
+fn fs_brdf(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n_dot_v = max(in.uv.x, 0.0001);
+    let roughness = max(in.uv.y, 0.04);
+    let v = vec3(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let n = vec3(0.0, 0.0, 1.0);
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < params.num_samples; i++) {
+        let h = importance_sample_ggx(hammersley(i, params.num_samples), n, roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = max(l.z, 0.0);
+        let n_dot_h = max(h.z, 0.0);
+        let v_dot_h = max(dot(v, h), 0.0);
+        if n_dot_l > 0.0 {
+            let g = geometry_smith_ibl(n_dot_v, n_dot_l, roughness);
+            let g_vis = g * v_dot_h / (n_dot_h * n_dot_v);
+            let fc = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fc) * g_vis;
+            bias += fc * g_vis;
+        }
+    }
+
+    return vec4(vec2(scale, bias) / f32(params.num_samples), 0.0, 1.0);
+}
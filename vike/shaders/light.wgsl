@@ -8,18 +8,6 @@ struct Camera {
 @group(0) @binding(0)
 var<uniform> camera: Camera;
 
-struct Light {
-    position: vec3<f32>,
-    color: vec3<f32>,
-    intensity: f32,
-}
-struct LightUniform {
-    numLights: u32,
-    lights: array<Light, 128>,
-}
-@group(1) @binding(0)
-var<uniform> lights: LightUniform;
-
 struct VertexInput {
     @location(0) position: vec3<f32>,
 };
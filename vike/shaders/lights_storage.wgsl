@@ -0,0 +1,18 @@
+@group(2) @binding(0)
+var<storage, read> lights: array<Light>;
+@group(2) @binding(5)
+var<storage, read> cluster_offsets: array<u32>;
+@group(2) @binding(6)
+var<storage, read> cluster_indices: array<u32>;
+
+fn get_light(i: u32) -> Light {
+    return lights[i];
+}
+
+fn get_cluster(cluster: u32) -> u32 {
+    return cluster_offsets[cluster];
+}
+
+fn get_light_index(i: u32) -> u32 {
+    return cluster_indices[i];
+}
@@ -0,0 +1,19 @@
+@group(2) @binding(0)
+var<uniform> lights: array<Light, 128>;
+@group(2) @binding(5)
+var<uniform> cluster_offsets: array<vec4<u32>, 64>;
+@group(2) @binding(6)
+var<uniform> cluster_indices: array<vec4<u32>, 256>;
+
+fn get_light(i: u32) -> Light {
+    return lights[i];
+}
+
+fn get_cluster(cluster: u32) -> u32 {
+    return cluster_offsets[cluster / 4u][cluster % 4u];
+}
+
+fn get_light_index(i: u32) -> u32 {
+    let word = cluster_indices[i / 16u][(i / 4u) % 4u];
+    return (word >> ((i % 4u) * 8u)) & 0xffu;
+}
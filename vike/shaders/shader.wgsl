@@ -10,22 +10,66 @@ var<uniform> camera: Camera;
 
 struct Light {
     position: vec3<f32>,
+    shadow_index: i32,
     color: vec3<f32>,
     intensity: f32,
+    direction: vec3<f32>,
+    kind: u32,
+    inner_cos: f32,
+    outer_cos: f32,
+    range: f32,
 }
-struct LightUniform {
-    numLights: u32,
-    lights: array<Light, 128>,
+
+struct Clusters {
+    dimensions: vec4<u32>,
+    num_lights: u32,
+    num_directional: u32,
+    z_params: vec4<f32>,
+    screen_size: vec4<f32>,
+    ambient: vec4<f32>,
+}
+@group(2) @binding(4)
+var<uniform> clusters: Clusters;
+
+struct ShadowUniform {
+    depth_bias: f32,
+    near: f32,
+    far: f32,
+    num_shadows: u32,
+    tiles_per_row: u32,
+    tile_size: f32,
+    texel_size: vec2<f32>,
 }
-@group(2) @binding(0)
-var<uniform> lights: LightUniform;
+@group(2) @binding(1)
+var t_shadow: texture_depth_2d;
+@group(2) @binding(2)
+var s_shadow: sampler_comparison;
+@group(2) @binding(3)
+var<uniform> shadow: ShadowUniform;
+
+struct Environment {
+    intensity: f32,
+    max_lod: f32,
+    enabled: u32,
+}
+@group(3) @binding(0)
+var t_irradiance: texture_cube<f32>;
+@group(3) @binding(1)
+var t_prefiltered: texture_cube<f32>;
+@group(3) @binding(2)
+var t_brdf_lut: texture_2d<f32>;
+@group(3) @binding(3)
+var s_environment: sampler;
+@group(3) @binding(4)
+var<uniform> environment: Environment;
+@group(3) @binding(5)
+var t_ambient_occlusion: texture_2d<f32>;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
-    @location(3) tangent: vec3<f32>,
-    @location(4) bitangent: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
 }
 
 struct InstanceInput {
@@ -42,10 +86,8 @@ struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) world_position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
-    @location(2) tangent_view_position: vec3<f32>,
-    @location(3) world_normal: vec3<f32>,
-    @location(4) world_tangent: vec3<f32>,
-    @location(5) world_bitangent: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_tangent: vec4<f32>,
  }
 
 @vertex
@@ -66,71 +108,266 @@ fn vs_main(
     );
 
     let world_normal = normalize(normal_matrix * model.normal);
-    let world_tangent = normalize(normal_matrix * model.tangent);
-    let world_bitangent = normalize(normal_matrix * model.bitangent);
-    let tangent_matrix = transpose(mat3x3<f32>(
-        world_tangent,
-        world_bitangent,
-        world_normal,
-    ));
-
+    let basis = mat3x3<f32>(model_matrix[0].xyz, model_matrix[1].xyz, model_matrix[2].xyz);
+    let world_tangent = normalize(basis * model.tangent.xyz);
+    // mirrored instances flip the handedness
+    let handedness = model.tangent.w * sign(determinant(basis));
     let world_position = model_matrix * vec4<f32>(model.position, 1.0);
 
     var out: VertexOutput;
     out.clip_position = camera.view_proj * world_position;
     out.world_position = world_position.xyz;
     out.tex_coords = model.tex_coords;
-    out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
     out.world_normal = world_normal;
-    out.world_tangent = world_tangent;
-    out.world_bitangent = world_bitangent;
+    out.world_tangent = vec4(world_tangent, handedness);
     return out;
 }
 
+struct Material {
+    base_color: vec4<f32>,
+    emissive: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    specular: f32,
+    occlusion_strength: f32,
+    normal_scale: f32,
+    alpha_cutoff: f32,
+}
+
 @group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
+var t_base_color: texture_2d<f32>;
 @group(0) @binding(1)
-var s_diffuse: sampler;
+var s_base_color: sampler;
 @group(0) @binding(2)
-var t_normal: texture_2d<f32>;
+var t_metallic_roughness: texture_2d<f32>;
 @group(0) @binding(3)
+var s_metallic_roughness: sampler;
+@group(0) @binding(4)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(5)
 var s_normal: sampler;
+@group(0) @binding(6)
+var t_emissive: texture_2d<f32>;
+@group(0) @binding(7)
+var s_emissive: sampler;
+@group(0) @binding(8)
+var t_occlusion: texture_2d<f32>;
+@group(0) @binding(9)
+var s_occlusion: sampler;
+@group(0) @binding(10)
+var<uniform> material: Material;
+
+const PI: f32 = 3.14159265359;
+
+const DEBUG_VIEW_NORMALS: u32 = 1u;
+const DEBUG_VIEW_NORMAL_MAP: u32 = 2u;
+const DEBUG_VIEW_UVS: u32 = 3u;
+const DEBUG_VIEW_ALBEDO: u32 = 4u;
+const DEBUG_VIEW_LIGHTING: u32 = 5u;
+const DEBUG_VIEW_DEPTH: u32 = 6u;
+const DEBUG_VIEW_OVERDRAW: u32 = 7u;
+const DEBUG_VIEW_WIREFRAME: u32 = 8u;
+
+const LIGHT_DIRECTIONAL: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
+fn cube_face(dir: vec3<f32>) -> u32 {
+    let a = abs(dir);
+    if a.x >= a.y && a.x >= a.z {
+        return select(1u, 0u, dir.x > 0.0);
+    } else if a.y >= a.z {
+        return select(3u, 2u, dir.y > 0.0);
+    }
+    return select(5u, 4u, dir.z > 0.0);
+}
+
+fn cube_face_basis(face: u32) -> mat2x3<f32> {
+    switch face {
+        case 0u: { return mat2x3(vec3(1.0, 0.0, 0.0), vec3(0.0, -1.0, 0.0)); }
+        case 1u: { return mat2x3(vec3(-1.0, 0.0, 0.0), vec3(0.0, -1.0, 0.0)); }
+        case 2u: { return mat2x3(vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, 1.0)); }
+        case 3u: { return mat2x3(vec3(0.0, -1.0, 0.0), vec3(0.0, 0.0, -1.0)); }
+        case 4u: { return mat2x3(vec3(0.0, 0.0, 1.0), vec3(0.0, -1.0, 0.0)); }
+        default: { return mat2x3(vec3(0.0, 0.0, -1.0), vec3(0.0, -1.0, 0.0)); }
+    }
+}
+
+fn shadow_factor(shadow_index: i32, light_position: vec3<f32>, world_position: vec3<f32>) -> f32 {
+    if shadow_index < 0 || u32(shadow_index) >= shadow.num_shadows {
+        return 1.0;
+    }
+
+    let dir = world_position - light_position;
+    let face = cube_face(dir);
+    let basis = cube_face_basis(face);
+    let forward = basis[0];
+    let side = normalize(cross(forward, basis[1]));
+    let up = cross(side, forward);
+
+    let w = dot(forward, dir);
+    if w <= shadow.near || w >= shadow.far {
+        return 1.0;
+    }
+    let ndc = vec2(dot(side, dir), dot(up, dir)) / w;
+    let half_texel = 0.5 / shadow.tile_size;
+    let tile_uv = clamp(vec2(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5), vec2(half_texel), vec2(1.0 - half_texel));
+
+    let tile = u32(shadow_index) * 6u + face;
+    let tile_origin = vec2(f32(tile % shadow.tiles_per_row), f32(tile / shadow.tiles_per_row));
+    let uv = (tile_origin + tile_uv) * shadow.tile_size * shadow.texel_size;
+
+    let biased = max(w - shadow.depth_bias, shadow.near);
+    let depth = shadow.far * (biased - shadow.near) / ((shadow.far - shadow.near) * biased);
+    return textureSampleCompareLevel(t_shadow, s_shadow, uv, depth);
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    let g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+struct Surface {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    view_dir: vec3<f32>,
+    n_dot_v: f32,
+    roughness: f32,
+    f0: vec3<f32>,
+    diffuse_color: vec3<f32>,
+}
+
+fn shade(light: Light, surface: Surface) -> vec3<f32> {
+    var light_dir = -light.direction;
+    var light_intensity = light.intensity;
+    if light.kind != LIGHT_DIRECTIONAL {
+        let light_offset = light.position - surface.position;
+        let light_distance = length(light_offset);
+        light_dir = light_offset / light_distance;
+        let falloff = clamp(1.0 - pow(light_distance / light.range, 4.0), 0.0, 1.0);
+        light_intensity = min(light.intensity / (light_distance * light_distance), 10.0) * falloff * falloff;
+    }
+    if light.kind == LIGHT_SPOT {
+        let cone = dot(-light_dir, light.direction);
+        light_intensity = light_intensity * smoothstep(light.outer_cos, light.inner_cos, cone);
+    }
+    let shadow_strength = shadow_factor(light.shadow_index, light.position, surface.position);
+
+    let half_dir = normalize(surface.view_dir + light_dir);
+    let n_dot_l = max(dot(surface.normal, light_dir), 0.0);
+    let n_dot_h = max(dot(surface.normal, half_dir), 0.0);
+    let h_dot_v = max(dot(half_dir, surface.view_dir), 0.0);
+
+    let fresnel = fresnel_schlick(h_dot_v, surface.f0);
+    let specular = distribution_ggx(n_dot_h, surface.roughness) * geometry_smith(surface.n_dot_v, n_dot_l, surface.roughness) * fresnel
+        / (4.0 * surface.n_dot_v * max(n_dot_l, 0.0001));
+    let diffuse = (1.0 - fresnel) * surface.diffuse_color / PI;
+
+    let radiance = light.color * light_intensity * shadow_strength;
+    return (diffuse + specular) * radiance * n_dot_l;
+}
+
+fn ambient(surface: Surface) -> vec3<f32> {
+    if environment.enabled == 0u {
+        return clusters.ambient.rgb * (surface.diffuse_color + surface.f0);
+    }
+
+    let fresnel = fresnel_schlick_roughness(surface.n_dot_v, surface.f0, surface.roughness);
+    let irradiance = textureSample(t_irradiance, s_environment, surface.normal).rgb;
+    let diffuse = (1.0 - fresnel) * irradiance * surface.diffuse_color;
+
+    let reflected = reflect(-surface.view_dir, surface.normal);
+    let prefiltered = textureSampleLevel(t_prefiltered, s_environment, reflected, surface.roughness * environment.max_lod).rgb;
+    let brdf = textureSample(t_brdf_lut, s_environment, vec2(surface.n_dot_v, surface.roughness)).rg;
+    let specular = prefiltered * (fresnel * brdf.x + brdf.y);
+
+    return (diffuse + specular) * environment.intensity;
+}
 
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
-    let object_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
-
-    let tangent_matrix = transpose(mat3x3<f32>(
-        in.world_tangent,
-        in.world_bitangent,
-        in.world_normal,
-    ));
-
-    var result: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
-    for (var i: u32 = 0; i < lights.numLights; i = i + 1) {
-        let light = lights.lights[i];
-        let light_distance = length(in.world_position - light.position);
-        let light_intensity = clamp(light.intensity / (light_distance * light_distance), 0.01, 10.0);
-
-        let ambient_strength = 0.01 / f32(lights.numLights);
-        let ambient_color = light.color * ambient_strength;
-
-        let tangent_normal = object_normal.xyz * 2.0 - 1.0;
-        let tangent_light_position = tangent_matrix * lights.lights[i].position;
-        let tangent_position = tangent_matrix * in.world_position;
-        let light_dir = normalize(tangent_light_position - tangent_position);
-        let view_dir = normalize(in.tangent_view_position - tangent_position);
-        let half_dir = normalize(view_dir + light_dir);
-
-        let diffuse_strength = max(dot(tangent_normal, light_dir), 0.0);
-        let diffuse_color = light.color * diffuse_strength * light_intensity;
-
-        let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), 16.0);
-        let specular_color = light.color * specular_strength * light_intensity;
-
-        result = result + (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+fn fs_main(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    let base_color = textureSample(t_base_color, s_base_color, in.tex_coords) * material.base_color;
+    let metallic_roughness = textureSample(t_metallic_roughness, s_metallic_roughness, in.tex_coords);
+    let tangent_normal = textureSample(t_normal, s_normal, in.tex_coords).xyz * 2.0 - 1.0;
+    let emissive = textureSample(t_emissive, s_emissive, in.tex_coords).rgb * material.emissive;
+    let occlusion_sample = textureSample(t_occlusion, s_occlusion, in.tex_coords).r;
+    if base_color.a < material.alpha_cutoff {
+        discard;
+    }
+
+    let metallic = clamp(material.metallic * metallic_roughness.b, 0.0, 1.0);
+    let roughness = clamp(material.roughness * metallic_roughness.g, 0.04, 1.0);
+    let occlusion = mix(1.0, occlusion_sample, material.occlusion_strength);
+
+    // MikkTSpace: the bitangent is rebuilt per pixel and the interpolated vectors are left
+    // unnormalized, matching the bakers
+    let bitangent = in.world_tangent.w * cross(in.world_normal, in.world_tangent.xyz);
+    let tbn = mat3x3<f32>(in.world_tangent.xyz, bitangent, in.world_normal);
+    // back faces are only drawn for double-sided materials, and should be lit from their own side
+    let facing = select(-1.0, 1.0, front_facing);
+    let normal = normalize(tbn * vec3(tangent_normal.xy * material.normal_scale, tangent_normal.z)) * facing;
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    let n_dot_v = max(dot(normal, view_dir), 0.0001);
+
+    switch DEBUG_VIEW {
+        case DEBUG_VIEW_NORMALS: { return vec4(normal * 0.5 + 0.5, 1.0); }
+        case DEBUG_VIEW_NORMAL_MAP: { return vec4(tangent_normal * 0.5 + 0.5, 1.0); }
+        case DEBUG_VIEW_UVS: { return vec4(fract(in.tex_coords), 0.0, 1.0); }
+        case DEBUG_VIEW_ALBEDO: { return vec4(base_color.rgb, 1.0); }
+        case DEBUG_VIEW_OVERDRAW: { return vec4(0.1, 0.04, 0.01, 1.0); }
+        case DEBUG_VIEW_WIREFRAME: { return vec4(0.0, 1.0, 0.5, 1.0); }
+        default: {}
+    }
+
+    let albedo = select(base_color.rgb, vec3(1.0), DEBUG_VIEW == DEBUG_VIEW_LIGHTING);
+    let f0 = mix(vec3(0.08 * material.specular), albedo, metallic);
+    let diffuse_color = albedo * (1.0 - metallic);
+
+    let surface = Surface(in.world_position, normal, view_dir, n_dot_v, roughness, f0, diffuse_color);
+
+    var result = vec3<f32>(0.0);
+    for (var i: u32 = 0u; i < clusters.num_directional; i = i + 1u) {
+        result = result + shade(get_light(i), surface);
+    }
+
+    let view_depth = -(camera.view * vec4(in.world_position, 1.0)).z;
+    if DEBUG_VIEW == DEBUG_VIEW_DEPTH {
+        let depth = log(view_depth / clusters.z_params.x) / log(clusters.z_params.y / clusters.z_params.x);
+        return vec4(vec3(1.0 - saturate(depth)), 1.0);
     }
-    
-    return vec4<f32>(result, object_color.a);
+    let tile = vec2<u32>(in.clip_position.xy / clusters.screen_size.xy * vec2<f32>(clusters.dimensions.xy));
+    let slice = u32(max(log(view_depth / clusters.z_params.x) * clusters.z_params.z, 0.0));
+    let cluster_id = min(vec3(tile, slice), clusters.dimensions.xyz - 1u);
+    let cluster = get_cluster((cluster_id.z * clusters.dimensions.y + cluster_id.y) * clusters.dimensions.x + cluster_id.x);
+    let offset = cluster >> 12u;
+    let count = cluster & 0xfffu;
+    for (var i: u32 = 0u; i < count; i = i + 1u) {
+        result = result + shade(get_light(get_light_index(offset + i)), surface);
+    }
+
+    let screen_uv = in.clip_position.xy / clusters.screen_size.xy;
+    let ambient_occlusion = textureSampleLevel(t_ambient_occlusion, s_environment, screen_uv, 0.0).r;
+    result = result + ambient(surface) * occlusion * ambient_occlusion;
+    if DEBUG_VIEW == DEBUG_VIEW_LIGHTING {
+        return vec4(result, 1.0);
+    }
+
+    return vec4<f32>(result + emissive, base_color.a);
 }
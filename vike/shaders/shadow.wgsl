@@ -0,0 +1,79 @@
+struct ShadowFace {
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> face: ShadowFace;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+}
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+}
+
+fn clip_position(model: VertexInput, instance: InstanceInput) -> vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+
+    return face.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    return clip_position(model, instance);
+}
+
+// Alpha-tested variant, matches the material bindings of shader.wgsl
+struct Material {
+    base_color: vec4<f32>,
+    emissive: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    specular: f32,
+    occlusion_strength: f32,
+    normal_scale: f32,
+    alpha_cutoff: f32,
+}
+
+@group(1) @binding(0)
+var t_base_color: texture_2d<f32>;
+@group(1) @binding(1)
+var s_base_color: sampler;
+@group(1) @binding(10)
+var<uniform> material: Material;
+
+struct MaskedOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_masked(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> MaskedOutput {
+    var out: MaskedOutput;
+    out.clip_position = clip_position(model, instance);
+    out.tex_coords = model.tex_coords;
+    return out;
+}
+
+@fragment
+fn fs_masked(in: MaskedOutput) {
+    let alpha = textureSample(t_base_color, s_base_color, in.tex_coords).a * material.base_color.a;
+    if alpha < material.alpha_cutoff {
+        discard;
+    }
+}
@@ -0,0 +1,64 @@
+struct Camera {
+    view_pos: vec4<f32>,
+    view: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Sky {
+    zenith: vec4<f32>,
+    horizon: vec4<f32>,
+    ground: vec4<f32>,
+    mode: u32,
+}
+@group(1) @binding(0)
+var sky_texture: texture_cube<f32>;
+@group(1) @binding(1)
+var sky_sampler: sampler;
+@group(1) @binding(2)
+var<uniform> sky: Sky;
+
+const SKY_GRADIENT: u32 = 0u;
+const SKY_CUBEMAP: u32 = 1u;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+
+    let uv = vec2<f32>(
+        f32((vi << 1u) & 2u),
+        f32(vi & 2u),
+    );
+    out.ndc = uv * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(out.ndc, 1.0, 1.0);
+    return out;
+}
+
+fn gradient(dir: vec3<f32>) -> vec3<f32> {
+    let exponent = sky.ground.w;
+    if dir.y >= 0.0 {
+        return mix(sky.horizon.rgb, sky.zenith.rgb, pow(dir.y, exponent));
+    }
+    return mix(sky.horizon.rgb, sky.ground.rgb, pow(-dir.y, exponent));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let view_dir = camera.inv_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let dir = normalize((camera.inv_view * vec4<f32>(view_dir.xyz, 0.0)).xyz);
+
+    if sky.mode == SKY_CUBEMAP {
+        return vec4<f32>(textureSample(sky_texture, sky_sampler, dir).rgb, 1.0);
+    }
+    return vec4<f32>(gradient(dir), 1.0);
+}
@@ -0,0 +1,112 @@
+const MAX_SAMPLES: u32 = 64u;
+
+struct Ssao {
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    radius: f32,
+    intensity: f32,
+    bias: f32,
+    num_samples: u32,
+    kernel: array<vec4<f32>, MAX_SAMPLES>,
+}
+
+@group(0) @binding(0)
+var t_depth: texture_2d<f32>;
+@group(0) @binding(1)
+var t_input: texture_2d<f32>;
+@group(0) @binding(2)
+var<uniform> ssao: Ssao;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+
+    out.uv = vec2<f32>(
+        f32((vi << 1u) & 2u),
+        f32(vi & 2u),
+    );
+    out.clip_position = vec4<f32>(out.uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv.y = 1.0 - out.uv.y;
+    return out;
+}
+
+fn view_position(coords: vec2<i32>) -> vec3<f32> {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let clamped = clamp(coords, vec2(0), vec2<i32>(size) - 1);
+    let depth = textureLoad(t_depth, clamped, 0).r;
+    let uv = (vec2<f32>(clamped) + 0.5) / size;
+    let ndc = vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let position = ssao.inv_proj * ndc;
+    return position.xyz / position.w;
+}
+
+// Pick the neighbour closest in depth on each axis so edges don't smear normals
+fn view_normal(coords: vec2<i32>, center: vec3<f32>) -> vec3<f32> {
+    let left = view_position(coords - vec2(1, 0));
+    let right = view_position(coords + vec2(1, 0));
+    let up = view_position(coords - vec2(0, 1));
+    let down = view_position(coords + vec2(0, 1));
+
+    let dx = select(center - left, right - center, abs(right.z - center.z) < abs(center.z - left.z));
+    let dy = select(center - up, down - center, abs(down.z - center.z) < abs(center.z - up.z));
+    return normalize(cross(dy, dx));
+}
+
+fn noise(coords: vec2<i32>) -> vec3<f32> {
+    let cell = vec2<u32>(coords) % 4u;
+    let angle = f32(cell.y * 4u + cell.x) * 0.39269908;
+    return vec3(cos(angle), sin(angle), 0.0);
+}
+
+@fragment
+fn fs_ssao(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coords = vec2<i32>(in.clip_position.xy);
+    if textureLoad(t_depth, coords, 0).r >= 1.0 {
+        return vec4(1.0);
+    }
+
+    let position = view_position(coords);
+    let normal = view_normal(coords, position);
+    let random = noise(coords);
+    let tangent = normalize(random - normal * dot(random, normal));
+    let tbn = mat3x3(tangent, cross(normal, tangent), normal);
+
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let num_samples = min(ssao.num_samples, MAX_SAMPLES);
+    var occlusion = 0.0;
+    for (var i = 0u; i < num_samples; i++) {
+        let sample_position = position + tbn * ssao.kernel[i].xyz * ssao.radius;
+        let clip = ssao.proj * vec4(sample_position, 1.0);
+        let ndc = clip.xy / clip.w;
+        let sample_coords = vec2<i32>(vec2(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5) * size);
+        let scene_depth = view_position(sample_coords).z;
+
+        let range = smoothstep(0.0, 1.0, ssao.radius / abs(position.z - scene_depth));
+        occlusion += select(0.0, 1.0, scene_depth >= sample_position.z + ssao.bias) * range;
+    }
+
+    let ao = 1.0 - occlusion / f32(max(num_samples, 1u));
+    return vec4(pow(ao, ssao.intensity), 0.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_blur(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coords = vec2<i32>(in.clip_position.xy);
+    let size = vec2<i32>(textureDimensions(t_input));
+
+    var result = 0.0;
+    for (var y = -2; y < 2; y++) {
+        for (var x = -2; x < 2; x++) {
+            let offset = clamp(coords + vec2(x, y), vec2(0), size - 1);
+            result += textureLoad(t_input, offset, 0).r;
+        }
+    }
+    return vec4(result / 16.0, 0.0, 0.0, 1.0);
+}
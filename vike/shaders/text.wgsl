@@ -0,0 +1,48 @@
+struct Screen {
+    size: vec2<f32>,
+}
+@group(0) @binding(0)
+var<uniform> screen: Screen;
+@group(0) @binding(1)
+var t_font: texture_2d<f32>;
+@group(0) @binding(2)
+var s_font: sampler;
+
+const CELL_SIZE: vec2<f32> = vec2(6.0, 8.0);
+const ATLAS_COLUMNS: u32 = 16u;
+const ATLAS_SIZE: vec2<f32> = CELL_SIZE * vec2(16.0, 6.0);
+
+struct Glyph {
+    @location(0) position: vec2<f32>,
+    @location(1) scale: f32,
+    @location(2) glyph: u32,
+    @location(3) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+    glyph: Glyph,
+) -> VertexOutput {
+    let corner = vec2(f32(vi & 1u), f32(vi >> 1u));
+    let pixel = glyph.position + corner * CELL_SIZE * glyph.scale;
+    let cell = vec2(f32(glyph.glyph % ATLAS_COLUMNS), f32(glyph.glyph / ATLAS_COLUMNS));
+
+    var out: VertexOutput;
+    out.clip_position = vec4(pixel / screen.size * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+    out.uv = (cell + corner) * CELL_SIZE / ATLAS_SIZE;
+    out.color = glyph.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = textureSample(t_font, s_font, in.uv).r;
+    return vec4(in.color.rgb, in.color.a * coverage);
+}
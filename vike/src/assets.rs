@@ -0,0 +1,283 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+
+use anyhow::{Result, anyhow, bail};
+
+pub type AssetFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<u8>>> + 'a>>;
+
+// Paths are '/' separated and relative to the root of the source
+pub trait AssetSource: Send + Sync {
+    fn load<'a>(&'a self, path: &'a str) -> AssetFuture<'a>;
+}
+
+// Matches where assets were looked up before sources were pluggable
+pub fn default_source() -> Box<dyn AssetSource> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            Box::new(FetchSource::new("http://localhost:8080/models"))
+        } else {
+            let root = std::env::var_os("OUT_DIR")
+                .map_or_else(Default::default, std::path::PathBuf::from)
+                .join("models");
+            Box::new(FileSource::new(root))
+        }
+    }
+}
+
+// Expands to the table written by `vike_build::embed_dir`, ready for
+// `MemorySource::embedded`
+#[macro_export]
+macro_rules! include_assets {
+    ($name:literal) => {
+        include!(concat!(env!("OUT_DIR"), "/", $name, ".rs"))
+    };
+}
+
+// Resolves "." and ".." so the same file always has the same key. Paths come
+// straight from asset files, so anything that climbs above the root (or names
+// a drive) is rejected rather than read
+pub fn normalize_path(path: &str) -> Result<String> {
+    let mut parts = Vec::new();
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                if parts.pop().is_none() {
+                    bail!("{:?} is outside the asset root", path);
+                }
+            }
+            _ if parts.is_empty() && part.ends_with(':') => {
+                bail!("{:?} is an absolute path", path);
+            }
+            _ => parts.push(part),
+        }
+    }
+    Ok(parts.join("/"))
+}
+
+// Resolves `path` relative to the directory containing `file`, the way OBJ
+// files reference their MTL files and MTL files reference their textures
+pub fn resolve_path(file: &str, path: &str) -> Result<String> {
+    let file = file.replace('\\', "/");
+    match file.rfind('/') {
+        Some(i) => normalize_path(&format!("{}/{}", &file[..i], path)),
+        None => normalize_path(path),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct FileSource {
+    root: std::path::PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileSource {
+    pub fn new(root: impl Into<std::path::PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &std::path::Path {
+        &self.root
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl AssetSource for FileSource {
+    fn load<'a>(&'a self, path: &'a str) -> AssetFuture<'a> {
+        Box::pin(async move {
+            let full_path = self.root.join(normalize_path(path)?);
+            async_fs::read(&full_path)
+                .await
+                .map_err(|err| anyhow!("failed to read {:?}: {}", full_path, err))
+        })
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MemorySource {
+    files: HashMap<String, Cow<'static, [u8]>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn embedded(files: &[(&'static str, &'static [u8])]) -> Result<Self> {
+        let mut source = Self::new();
+        for &(path, data) in files {
+            source.insert(path, data)?;
+        }
+        Ok(source)
+    }
+
+    pub fn insert(&mut self, path: &str, data: impl Into<Cow<'static, [u8]>>) -> Result<()> {
+        self.files.insert(normalize_path(path)?, data.into());
+        Ok(())
+    }
+
+    pub fn remove(&mut self, path: &str) -> bool {
+        normalize_path(path).is_ok_and(|path| self.files.remove(&path).is_some())
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        normalize_path(path).is_ok_and(|path| self.files.contains_key(&path))
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(String::as_str)
+    }
+}
+
+impl AssetSource for MemorySource {
+    fn load<'a>(&'a self, path: &'a str) -> AssetFuture<'a> {
+        Box::pin(async move {
+            self.files
+                .get(&normalize_path(path)?)
+                .map(|data| data.to_vec())
+                .ok_or_else(|| anyhow!("{:?} is not in the memory source", path))
+        })
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub struct FetchSource {
+    base_url: String,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl FetchSource {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    async fn fetch(&self, path: &str) -> Result<web_sys::Response> {
+        use wasm_bindgen::JsCast;
+        use wasm_bindgen_futures::JsFuture;
+        use web_sys::js_sys;
+
+        let url = format!("{}/{}", self.base_url, normalize_path(path)?);
+
+        let global = js_sys::global();
+
+        let fetch_fn = js_sys::Function::from(
+            js_sys::Reflect::get(&global, &"fetch".into())
+                .map_err(|_| anyhow!("fetch not found in global scope"))?,
+        );
+
+        let resp_value = JsFuture::from(
+            fetch_fn
+                .call1(&global, &url.into())
+                .map_err(|e| anyhow!("JS fetch call error: {:?}", e))?
+                .dyn_into::<js_sys::Promise>()
+                .map_err(|_| anyhow!("JS fetch did not return a Promise"))?,
+        )
+        .await
+        .map_err(|e| anyhow!("JS fetch error: {:?}", e))?;
+
+        let resp: web_sys::Response = resp_value
+            .dyn_into()
+            .map_err(|_| anyhow!("JS fetch not a Response"))?;
+
+        if !resp.ok() {
+            return Err(anyhow!("JS fetch HTTP error: {}", resp.status()));
+        }
+
+        Ok(resp)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl AssetSource for FetchSource {
+    fn load<'a>(&'a self, path: &'a str) -> AssetFuture<'a> {
+        Box::pin(async move {
+            use wasm_bindgen_futures::JsFuture;
+            use web_sys::js_sys;
+
+            let resp = self.fetch(path).await?;
+            let array_buffer = JsFuture::from(
+                resp.array_buffer()
+                    .map_err(|e| anyhow!("JS load buffer error: {:?}", e))?,
+            )
+            .await
+            .map_err(|e| anyhow!("JS await error: {:?}", e))?;
+
+            Ok(js_sys::Uint8Array::new(&array_buffer).to_vec())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_path_resolves_dots() {
+        assert_eq!(normalize_path("a/./b/../c.png").unwrap(), "a/c.png");
+        assert_eq!(normalize_path("/a//b\\c.obj").unwrap(), "a/b/c.obj");
+        assert_eq!(normalize_path("a/..").unwrap(), "");
+    }
+
+    #[test]
+    fn normalize_path_rejects_escapes() {
+        assert!(normalize_path("..").is_err());
+        assert!(normalize_path("../../x").is_err());
+        assert!(normalize_path("a/../../x").is_err());
+        assert!(normalize_path("C:\\Windows\\win.ini").is_err());
+    }
+
+    #[test]
+    fn resolve_path_is_relative_to_the_file() {
+        assert_eq!(resolve_path("cube.obj", "cube.mtl").unwrap(), "cube.mtl");
+        assert_eq!(
+            resolve_path("models/cube.obj", "../materials/cube.mtl").unwrap(),
+            "materials/cube.mtl"
+        );
+        assert!(resolve_path("cube.obj", "../cube.mtl").is_err());
+    }
+
+    #[test]
+    fn resolve_path_handles_nested_parents_and_backslashes() {
+        assert_eq!(
+            resolve_path("pack/models/trees/bark.mtl", "../../textures/./bark.png").unwrap(),
+            "pack/textures/bark.png"
+        );
+        assert_eq!(
+            resolve_path("pack\\models\\bark.obj", "..\\materials\\bark.mtl").unwrap(),
+            "pack/materials/bark.mtl"
+        );
+    }
+
+    #[test]
+    fn embedded_files_are_found_by_any_equivalent_path() {
+        static FILES: &[(&str, &[u8])] = &[
+            ("models/cube.obj", b"obj"),
+            ("textures/cube-diffuse.jpg", b"jpg"),
+        ];
+        let source = MemorySource::embedded(FILES).unwrap();
+
+        assert!(source.contains("models/cube.obj"));
+        assert!(source.contains("models\\..\\textures\\cube-diffuse.jpg"));
+        assert!(!source.contains("cube.obj"));
+        assert!(!source.contains("../models/cube.obj"));
+
+        let texture = resolve_path("models/cube.obj", "../textures/cube-diffuse.jpg").unwrap();
+        let data = futures_lite::future::block_on(source.load(&texture)).unwrap();
+        assert_eq!(data, b"jpg");
+        assert!(futures_lite::future::block_on(source.load("models/missing.obj")).is_err());
+    }
+
+    #[test]
+    fn embedded_paths_outside_the_root_are_rejected() {
+        static FILES: &[(&str, &[u8])] = &[("../secret.txt", b"")];
+        assert!(MemorySource::embedded(FILES).is_err());
+    }
+}
@@ -0,0 +1,368 @@
+use crate::{hdr::HdrPipeline, stats::FrameStats};
+
+const MAX_MIPS: u32 = 6;
+
+#[derive(Clone, Debug)]
+pub struct BloomSettings {
+    pub threshold: f32,
+    pub soft_knee: f32,
+    pub intensity: f32,
+    pub radius: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            soft_knee: 0.5,
+            intensity: 0.04,
+            radius: 0.005,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    radius: f32,
+    _padding: u32,
+}
+
+impl From<&BloomSettings> for BloomUniform {
+    fn from(settings: &BloomSettings) -> Self {
+        Self {
+            threshold: settings.threshold,
+            knee: settings.threshold * settings.soft_knee.clamp(0.0, 1.0),
+            radius: settings.radius,
+            _padding: 0,
+        }
+    }
+}
+
+pub struct BloomPipeline {
+    downsample_first: wgpu::RenderPipeline,
+    downsample: wgpu::RenderPipeline,
+    upsample: wgpu::RenderPipeline,
+    composite: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    mips: Vec<wgpu::TextureView>,
+    hdr_bind_group: wgpu::BindGroup,
+    mip_bind_groups: Vec<wgpu::BindGroup>,
+    settings: BloomSettings,
+}
+
+impl BloomPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        hdr_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+        settings: BloomSettings,
+    ) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("bloom_bind_group_layout"),
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/bloom.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: HdrPipeline::FORMAT,
+                        blend,
+                        write_mask: wgpu::ColorWrites::COLOR,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let additive = |src_factor| {
+            Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent::OVER,
+            })
+        };
+
+        let downsample_first = create_pipeline("fs_downsample_first", None);
+        let downsample = create_pipeline("fs_downsample", None);
+        let upsample = create_pipeline("fs_upsample", additive(wgpu::BlendFactor::One));
+        let composite = create_pipeline("fs_upsample", additive(wgpu::BlendFactor::Constant));
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Bloom Uniform Buffer"),
+            size: std::mem::size_of::<BloomUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let hdr_bind_group =
+            Self::create_bind_group(device, &layout, hdr_view, &sampler, &uniform_buffer);
+        let mut bloom = Self {
+            downsample_first,
+            downsample,
+            upsample,
+            composite,
+            layout,
+            sampler,
+            uniform_buffer,
+            mips: Vec::new(),
+            hdr_bind_group,
+            mip_bind_groups: Vec::new(),
+            settings,
+        };
+        bloom.resize(device, hdr_view, width, height);
+        bloom
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("bloom_bind_group"),
+        })
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        hdr_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        let (width, height) = ((width / 2).max(1), (height / 2).max(1));
+        let mip_level_count = (width.min(height).ilog2() + 1).min(MAX_MIPS);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("bloom_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HdrPipeline::FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+
+        self.mips = (0..mip_level_count)
+            .map(|mip| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        self.hdr_bind_group = Self::create_bind_group(
+            device,
+            &self.layout,
+            hdr_view,
+            &self.sampler,
+            &self.uniform_buffer,
+        );
+        self.mip_bind_groups = self
+            .mips
+            .iter()
+            .map(|view| {
+                Self::create_bind_group(
+                    device,
+                    &self.layout,
+                    view,
+                    &self.sampler,
+                    &self.uniform_buffer,
+                )
+            })
+            .collect();
+    }
+
+    pub fn settings(&self) -> &BloomSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: BloomSettings) {
+        self.settings = settings;
+    }
+
+    fn pass(
+        encoder: &mut wgpu::CommandEncoder,
+        stats: &mut FrameStats,
+        target: &wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+        blend_constant: Option<f64>,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("bloom_render_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        if let Some(constant) = blend_constant {
+            pass.set_blend_constant(wgpu::Color {
+                r: constant,
+                g: constant,
+                b: constant,
+                a: constant,
+            });
+        }
+        pass.draw(0..3, 0..1);
+        stats.pipeline();
+        stats.draw(1);
+    }
+
+    pub fn process(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        hdr_view: &wgpu::TextureView,
+        stats: &mut FrameStats,
+    ) {
+        if self.settings.intensity <= 0.0 {
+            return;
+        }
+
+        stats.write(
+            queue,
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[BloomUniform::from(&self.settings)]),
+        );
+
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        Self::pass(
+            encoder,
+            stats,
+            &self.mips[0],
+            clear,
+            &self.downsample_first,
+            &self.hdr_bind_group,
+            None,
+        );
+        for mip in 1..self.mips.len() {
+            Self::pass(
+                encoder,
+                stats,
+                &self.mips[mip],
+                clear,
+                &self.downsample,
+                &self.mip_bind_groups[mip - 1],
+                None,
+            );
+        }
+        for mip in (0..self.mips.len() - 1).rev() {
+            Self::pass(
+                encoder,
+                stats,
+                &self.mips[mip],
+                wgpu::LoadOp::Load,
+                &self.upsample,
+                &self.mip_bind_groups[mip + 1],
+                None,
+            );
+        }
+        Self::pass(
+            encoder,
+            stats,
+            hdr_view,
+            wgpu::LoadOp::Load,
+            &self.composite,
+            &self.mip_bind_groups[0],
+            Some(self.settings.intensity as f64),
+        );
+    }
+}
@@ -1,10 +1,12 @@
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
 use std::f32::consts::FRAC_PI_2;
 use std::time::Duration;
 use winit::dpi::PhysicalPosition;
 use winit::event::*;
 use winit::keyboard::KeyCode;
 
+use crate::game_object::Aabb;
+
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
 
 #[derive(Debug)]
@@ -61,6 +63,42 @@ impl Projection {
     pub fn calc_matrix(&self) -> Mat4 {
         Mat4::perspective_rh(self.fovy, self.aspect, self.znear, self.zfar)
     }
+
+    pub fn znear(&self) -> f32 {
+        self.znear
+    }
+
+    pub fn zfar(&self) -> f32 {
+        self.zfar
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    pub fn from_view_proj(view_proj: Mat4) -> Self {
+        let (x, y, z, w) = (
+            view_proj.row(0),
+            view_proj.row(1),
+            view_proj.row(2),
+            view_proj.row(3),
+        );
+        let planes =
+            [w + x, w - x, w + y, w - y, z, w - z].map(|plane| plane / plane.xyz().length());
+
+        Self { planes }
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        let (center, extents) = (aabb.center(), aabb.extents());
+        self.planes.iter().all(|plane| {
+            let radius = extents.dot(plane.xyz().abs());
+            plane.xyz().dot(center) + plane.w >= -radius
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -208,3 +246,43 @@ impl CameraUniform {
         self.inv_view = view.transpose().to_cols_array_2d();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Looking down -Z from (0, 0, 5) with a 90 degree field of view, so the
+    // side planes at the origin are at x = +-5
+    fn frustum() -> Frustum {
+        let view = Mat4::look_at_rh(Vec3::new(0.0, 0.0, 5.0), Vec3::ZERO, Vec3::Y);
+        let proj = Mat4::perspective_rh(FRAC_PI_2, 1.0, 0.1, 50.0);
+        Frustum::from_view_proj(proj * view)
+    }
+
+    fn cube(center: Vec3, half: f32) -> Aabb {
+        Aabb::new(center - half, center + half)
+    }
+
+    #[test]
+    fn box_in_front_is_kept() {
+        assert!(frustum().intersects_aabb(&cube(Vec3::ZERO, 1.0)));
+        assert!(frustum().intersects_aabb(&cube(Vec3::new(0.0, 0.0, -40.0), 1.0)));
+    }
+
+    #[test]
+    fn box_behind_the_camera_is_culled() {
+        assert!(!frustum().intersects_aabb(&cube(Vec3::new(0.0, 0.0, 10.0), 1.0)));
+    }
+
+    #[test]
+    fn box_beyond_the_far_plane_is_culled() {
+        assert!(!frustum().intersects_aabb(&cube(Vec3::new(0.0, 0.0, -50.0), 1.0)));
+    }
+
+    #[test]
+    fn box_straddling_a_side_plane_is_kept() {
+        assert!(frustum().intersects_aabb(&cube(Vec3::new(5.0, 0.0, 0.0), 0.5)));
+        assert!(frustum().intersects_aabb(&cube(Vec3::new(0.0, -5.0, 0.0), 0.5)));
+        assert!(!frustum().intersects_aabb(&cube(Vec3::new(7.0, 0.0, 0.0), 0.5)));
+    }
+}
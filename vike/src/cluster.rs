@@ -0,0 +1,409 @@
+use glam::{Mat4, UVec3, Vec3, Vec4, Vec4Swizzles};
+
+use crate::{
+    MAX_LIGHTS,
+    game_object::{Light, LightKind},
+    stats::FrameStats,
+};
+
+const UNIFORM_CLUSTERS: UVec3 = UVec3::new(8, 4, 8);
+const UNIFORM_INDEX_CAPACITY: usize = 4096;
+const OFFSET_BITS: u32 = 12;
+// offsets share a word with the count, so even a storage buffer can only
+// address what fits in the remaining bits
+const STORAGE_INDEX_CAPACITY: usize = (1 << (32 - OFFSET_BITS)) - 1;
+
+#[derive(Clone, Debug)]
+pub struct ClusterSettings {
+    pub dimensions: UVec3,
+}
+
+impl Default for ClusterSettings {
+    fn default() -> Self {
+        Self {
+            dimensions: UVec3::new(16, 9, 24),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ClusterUniform {
+    dimensions: [u32; 4],
+    num_lights: u32,
+    num_directional: u32,
+    _padding: [u32; 2],
+    z_params: [f32; 4],
+    screen_size: [f32; 4],
+    ambient: [f32; 4],
+}
+
+pub struct ClusteredLights {
+    storage: bool,
+    settings: ClusterSettings,
+    uniform: ClusterUniform,
+    uniform_buffer: wgpu::Buffer,
+    light_buffer: wgpu::Buffer,
+    offset_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    lights: Vec<Light>,
+    offsets: Vec<u32>,
+    indices: Vec<u32>,
+}
+
+impl ClusteredLights {
+    pub fn new(device: &wgpu::Device, settings: ClusterSettings) -> Self {
+        let storage = device.limits().max_storage_buffers_per_shader_stage >= 3;
+        let settings = if storage {
+            settings
+        } else {
+            ClusterSettings {
+                dimensions: UNIFORM_CLUSTERS,
+            }
+        };
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cluster Uniform Buffer"),
+            size: std::mem::size_of::<ClusterUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let num_clusters = settings.dimensions.element_product() as u64;
+        let (light_size, offset_size, index_size) = if storage {
+            (1, num_clusters, 1)
+        } else {
+            (
+                MAX_LIGHTS as u64,
+                num_clusters,
+                UNIFORM_INDEX_CAPACITY as u64 / 4,
+            )
+        };
+        let light_buffer = Self::create_buffer(
+            device,
+            storage,
+            light_size * std::mem::size_of::<Light>() as u64,
+            "Light Buffer",
+        );
+        let offset_buffer =
+            Self::create_buffer(device, storage, offset_size * 4, "Cluster Offsets");
+        let index_buffer = Self::create_buffer(device, storage, index_size * 4, "Cluster Indices");
+
+        Self {
+            storage,
+            settings,
+            uniform: ClusterUniform::default(),
+            uniform_buffer,
+            light_buffer,
+            offset_buffer,
+            index_buffer,
+            lights: Vec::new(),
+            offsets: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, storage: bool, size: u64, label: &str) -> wgpu::Buffer {
+        let usage = if storage {
+            wgpu::BufferUsages::STORAGE
+        } else {
+            wgpu::BufferUsages::UNIFORM
+        };
+
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: size.max(16),
+            usage: usage | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn layout_entries(&self) -> [wgpu::BindGroupLayoutEntry; 4] {
+        let ty = if self.storage {
+            wgpu::BufferBindingType::Storage { read_only: true }
+        } else {
+            wgpu::BufferBindingType::Uniform
+        };
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        [
+            entry(0, ty),
+            entry(4, wgpu::BufferBindingType::Uniform),
+            entry(5, ty),
+            entry(6, ty),
+        ]
+    }
+
+    pub fn bind_group_entries(&self) -> [wgpu::BindGroupEntry<'_>; 4] {
+        [
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: self.light_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: self.uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: self.offset_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: self.index_buffer.as_entire_binding(),
+            },
+        ]
+    }
+
+    pub fn shader_header(&self) -> &'static str {
+        if self.storage {
+            include_str!("../shaders/lights_storage.wgsl")
+        } else {
+            include_str!("../shaders/lights_uniform.wgsl")
+        }
+    }
+
+    pub fn settings(&self) -> &ClusterSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, device: &wgpu::Device, settings: ClusterSettings) {
+        if !self.storage {
+            log::warn!("cluster dimensions are fixed without storage buffer support");
+            return;
+        }
+
+        let num_clusters = settings.dimensions.element_product() as u64;
+        if num_clusters != self.settings.dimensions.element_product() as u64 {
+            self.offset_buffer =
+                Self::create_buffer(device, true, num_clusters * 4, "Cluster Offsets");
+        }
+        self.settings = settings;
+    }
+
+    pub fn num_lights(&self) -> usize {
+        self.lights.len()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lights: &[Light],
+        view: Mat4,
+        proj: Mat4,
+        (near, far): (f32, f32),
+        size: (u32, u32),
+        stats: &mut FrameStats,
+    ) -> bool {
+        self.lights.clear();
+        self.lights
+            .extend(lights.iter().filter(|l| l.kind == LightKind::DIRECTIONAL));
+        let num_directional = self.lights.len();
+        self.lights
+            .extend(lights.iter().filter(|l| l.kind != LightKind::DIRECTIONAL));
+        if !self.storage && self.lights.len() > MAX_LIGHTS {
+            log::warn!(
+                "{} lights exceed the uniform light limit of {}",
+                self.lights.len(),
+                MAX_LIGHTS
+            );
+            self.lights.truncate(MAX_LIGHTS);
+        }
+
+        self.assign(view, proj, near, far, num_directional);
+
+        let mut reallocated = false;
+        if self.storage {
+            let light_bytes = std::mem::size_of_val(self.lights.as_slice()) as u64;
+            if light_bytes > self.light_buffer.size() {
+                let size = light_bytes.next_power_of_two();
+                self.light_buffer = Self::create_buffer(device, true, size, "Light Buffer");
+                reallocated = true;
+            }
+            let index_bytes = (self.indices.len() * 4) as u64;
+            if index_bytes > self.index_buffer.size() {
+                let size = index_bytes.next_power_of_two();
+                self.index_buffer = Self::create_buffer(device, true, size, "Cluster Indices");
+                reallocated = true;
+            }
+            stats.write(
+                queue,
+                &self.index_buffer,
+                0,
+                bytemuck::cast_slice(&self.indices),
+            );
+        } else {
+            let mut packed = vec![0u8; UNIFORM_INDEX_CAPACITY];
+            for (byte, index) in packed.iter_mut().zip(&self.indices) {
+                *byte = *index as u8;
+            }
+            stats.write(queue, &self.index_buffer, 0, &packed);
+        }
+        if !self.lights.is_empty() {
+            stats.write(
+                queue,
+                &self.light_buffer,
+                0,
+                bytemuck::cast_slice(&self.lights),
+            );
+        }
+        stats.write(
+            queue,
+            &self.offset_buffer,
+            0,
+            bytemuck::cast_slice(&self.offsets),
+        );
+
+        let dimensions = self.settings.dimensions;
+        let ambient = self
+            .lights
+            .iter()
+            .fold(Vec3::ZERO, |sum, l| sum + Vec3::from(l.color))
+            * 0.01
+            / self.lights.len().max(1) as f32;
+        self.uniform = ClusterUniform {
+            dimensions: [dimensions.x, dimensions.y, dimensions.z, 0],
+            num_lights: self.lights.len() as u32,
+            num_directional: num_directional as u32,
+            _padding: [0; 2],
+            z_params: [near, far, dimensions.z as f32 / (far / near).ln(), 0.0],
+            screen_size: [size.0 as f32, size.1 as f32, 0.0, 0.0],
+            ambient: ambient.extend(1.0).into(),
+        };
+        stats.write(
+            queue,
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+
+        reallocated
+    }
+
+    fn assign(&mut self, view: Mat4, proj: Mat4, near: f32, far: f32, num_directional: usize) {
+        let dimensions = self.settings.dimensions;
+        let num_clusters = dimensions.element_product() as usize;
+        let z_scale = dimensions.z as f32 / (far / near).ln();
+        let slice = |depth: f32| {
+            let z = ((depth.max(near) / near).ln() * z_scale).floor() as i32;
+            z.clamp(0, dimensions.z as i32 - 1) as u32
+        };
+
+        let mut bounds = Vec::with_capacity(self.lights.len() - num_directional);
+        for (i, light) in self.lights.iter().enumerate().skip(num_directional) {
+            let center = (view * Vec3::from(light.position).extend(1.0)).xyz();
+            let (depth_min, depth_max) = (-center.z - light.range, -center.z + light.range);
+            if depth_max < near {
+                continue;
+            }
+
+            let (mut min, mut max) = (Vec3::splat(-1.0), Vec3::splat(1.0));
+            if depth_min > near {
+                (min, max) = (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN));
+                for corner in 0..8 {
+                    let offset = Vec3::new(
+                        if corner & 1 == 0 { -1.0 } else { 1.0 },
+                        if corner & 2 == 0 { -1.0 } else { 1.0 },
+                        if corner & 4 == 0 { -1.0 } else { 1.0 },
+                    );
+                    let clip = proj * Vec4::from((center + offset * light.range, 1.0));
+                    let ndc = clip.xyz() / clip.w;
+                    min = min.min(ndc);
+                    max = max.max(ndc);
+                }
+                if min.x > 1.0 || min.y > 1.0 || max.x < -1.0 || max.y < -1.0 {
+                    continue;
+                }
+            }
+
+            let tile = |ndc: f32, count: u32, flip: bool| {
+                let t = if flip {
+                    0.5 - ndc * 0.5
+                } else {
+                    ndc * 0.5 + 0.5
+                };
+                ((t * count as f32).floor() as i32).clamp(0, count as i32 - 1) as u32
+            };
+            bounds.push((
+                i as u32,
+                UVec3::new(
+                    tile(min.x, dimensions.x, false),
+                    tile(max.y, dimensions.y, true),
+                    slice(depth_min),
+                ),
+                UVec3::new(
+                    tile(max.x, dimensions.x, false),
+                    tile(min.y, dimensions.y, true),
+                    slice(depth_max),
+                ),
+            ));
+        }
+
+        let cluster_index =
+            |x: u32, y: u32, z: u32| ((z * dimensions.y + y) * dimensions.x + x) as usize;
+        let mut counts = vec![0u32; num_clusters];
+        for (_, min, max) in &bounds {
+            for z in min.z..=max.z {
+                for y in min.y..=max.y {
+                    for x in min.x..=max.x {
+                        counts[cluster_index(x, y, z)] += 1;
+                    }
+                }
+            }
+        }
+
+        let capacity = if self.storage {
+            STORAGE_INDEX_CAPACITY
+        } else {
+            UNIFORM_INDEX_CAPACITY
+        };
+        let max_count = (1 << OFFSET_BITS) - 1;
+        let mut cursors = vec![0u32; num_clusters];
+        let mut total = 0u32;
+        for (count, cursor) in counts.iter_mut().zip(cursors.iter_mut()) {
+            *count = (*count)
+                .min(max_count)
+                .min((capacity - total as usize) as u32);
+            *cursor = total;
+            total += *count;
+        }
+        if total as usize == capacity {
+            log::warn!("cluster light index list is full, some lights were dropped");
+        }
+
+        self.offsets.clear();
+        self.offsets.extend(
+            cursors
+                .iter()
+                .zip(&counts)
+                .map(|(offset, count)| offset << OFFSET_BITS | count),
+        );
+        self.indices.clear();
+        self.indices.resize(total as usize, 0);
+        for (light, min, max) in &bounds {
+            for z in min.z..=max.z {
+                for y in min.y..=max.y {
+                    for x in min.x..=max.x {
+                        let cluster = cluster_index(x, y, z);
+                        let end = (self.offsets[cluster] >> OFFSET_BITS) + counts[cluster];
+                        if cursors[cluster] < end {
+                            self.indices[cursors[cluster] as usize] = *light;
+                            cursors[cluster] += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 
-use crate::renderer::Renderer;
+use glam::{Mat4, Vec2, Vec3};
+
+use crate::{game_object::Aabb, stats::FrameStats, text::TextPipeline, texture::Texture};
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
@@ -45,14 +47,45 @@ const POSITION_COLOR_LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBuf
     ],
 };
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DebugView {
+    #[default]
+    None,
+    Normals,
+    NormalMap,
+    Uvs,
+    Albedo,
+    Lighting,
+    Depth,
+    Overdraw,
+    Wireframe,
+}
+
+impl DebugView {
+    pub fn shader_header(&self) -> String {
+        format!("const DEBUG_VIEW: u32 = {}u;", *self as u32)
+    }
+}
+
+const MIN_VERTICES: u64 = 1024;
+const VERTEX_SIZE: u64 = std::mem::size_of::<PositionColor>() as u64;
+const CIRCLE_SEGMENTS: usize = 32;
+
 pub struct Debug {
-    color_lines: wgpu::RenderPipeline,
+    depth_tested: wgpu::RenderPipeline,
+    overlay: wgpu::RenderPipeline,
     axis: wgpu::Buffer,
+    vertex_buffer: wgpu::Buffer,
+    lines: Vec<PositionColor>,
+    overlay_lines: Vec<PositionColor>,
+    depth_test: bool,
+    text: TextPipeline,
 }
 
 impl Debug {
     pub fn new(
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         camera_layout: &wgpu::BindGroupLayout,
         color_format: wgpu::TextureFormat,
     ) -> Self {
@@ -62,23 +95,248 @@ impl Debug {
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::VERTEX,
         });
 
-        let shader = wgpu::include_wgsl!("../shaders/debug.wgsl");
+        let shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/debug.wgsl"));
         let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
             bind_group_layouts: &[camera_layout],
             push_constant_ranges: &[],
         });
-        let color_lines = Renderer::create_render_pipeline(
-            device,
-            &layout,
-            color_format,
-            None,
-            &[POSITION_COLOR_LAYOUT],
-            wgpu::PrimitiveTopology::LineList,
-            shader,
+        // The debug pass reads the scene depth but never writes to it
+        let create_pipeline = |label, depth_compare| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[POSITION_COLOR_LAYOUT],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::LineList,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let depth_tested = create_pipeline("debug_lines", wgpu::CompareFunction::LessEqual);
+        let overlay = create_pipeline("debug_overlay", wgpu::CompareFunction::Always);
+
+        Self {
+            depth_tested,
+            overlay,
+            axis,
+            vertex_buffer: Self::create_vertex_buffer(device, MIN_VERTICES),
+            lines: Vec::new(),
+            overlay_lines: Vec::new(),
+            depth_test: true,
+            text: TextPipeline::new(device, queue, color_format, Some(Texture::DEPTH_FORMAT)),
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("debug_lines"),
+            size: capacity * VERTEX_SIZE,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn depth_test(&self) -> bool {
+        self.depth_test
+    }
+
+    // Applies to everything queued after it, until changed again
+    pub fn set_depth_test(&mut self, depth_test: bool) {
+        self.depth_test = depth_test;
+    }
+
+    pub fn line(&mut self, start: Vec3, end: Vec3, color: Vec3) {
+        let lines = if self.depth_test {
+            &mut self.lines
+        } else {
+            &mut self.overlay_lines
+        };
+        lines.push(PositionColor {
+            position: start.into(),
+            color: color.into(),
+        });
+        lines.push(PositionColor {
+            position: end.into(),
+            color: color.into(),
+        });
+    }
+
+    pub fn arrow(&mut self, start: Vec3, end: Vec3, color: Vec3) {
+        self.line(start, end, color);
+
+        let Some(direction) = (end - start).try_normalize() else {
+            return;
+        };
+        let head = start.distance(end) * 0.2;
+        let (u, v) = direction.any_orthonormal_pair();
+        let base = end - direction * head;
+        for side in [u, -u, v, -v] {
+            self.line(end, base + side * head * 0.5, color);
+        }
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, color: Vec3) {
+        let corner = |i: usize| {
+            Vec3::select(
+                glam::BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0),
+                aabb.max,
+                aabb.min,
+            )
+        };
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corner(i), corner(i | bit), color);
+                }
+            }
+        }
+    }
+
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: Vec3) {
+        self.circle(center, Vec3::X * radius, Vec3::Y * radius, color);
+        self.circle(center, Vec3::Y * radius, Vec3::Z * radius, color);
+        self.circle(center, Vec3::Z * radius, Vec3::X * radius, color);
+    }
+
+    pub fn circle(&mut self, center: Vec3, u: Vec3, v: Vec3, color: Vec3) {
+        let point = |i: usize| {
+            let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+            center + u * angle.cos() + v * angle.sin()
+        };
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+
+    pub fn frustum(&mut self, view_proj: Mat4, color: Vec3) {
+        let inverse = view_proj.inverse();
+        let corner = |i: usize| {
+            let x = if i & 1 == 0 { -1.0 } else { 1.0 };
+            let y = if i & 2 == 0 { -1.0 } else { 1.0 };
+            let z = if i & 4 == 0 { 0.0 } else { 1.0 };
+            inverse.project_point3(Vec3::new(x, y, z))
+        };
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corner(i), corner(i | bit), color);
+                }
+            }
+        }
+    }
+
+    // A square grid on the XZ plane, `size` units across
+    pub fn grid(&mut self, center: Vec3, size: f32, divisions: u32, color: Vec3) {
+        let divisions = divisions.max(1);
+        let half = size * 0.5;
+        for i in 0..=divisions {
+            let offset = i as f32 / divisions as f32 * size - half;
+            self.line(
+                center + Vec3::new(offset, 0.0, -half),
+                center + Vec3::new(offset, 0.0, half),
+                color,
+            );
+            self.line(
+                center + Vec3::new(-half, 0.0, offset),
+                center + Vec3::new(half, 0.0, offset),
+                color,
+            );
+        }
+    }
+
+    pub fn axes(&mut self, transform: Mat4, length: f32) {
+        let origin = transform.transform_point3(Vec3::ZERO);
+        for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+            self.line(origin, transform.transform_point3(axis * length), axis);
+        }
+    }
+
+    // Screen-space text, positioned in pixels from the top-left corner
+    pub fn text(&mut self, position: Vec2, scale: f32, text: &str, color: Vec3) {
+        self.text.queue(position, scale, text, color);
+    }
+
+    pub fn needs_depth(&self) -> bool {
+        !self.lines.is_empty()
+    }
+
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: (u32, u32),
+        stats: &mut FrameStats,
+    ) {
+        self.text.prepare(device, queue, size, stats);
+
+        let required = (self.lines.len() + self.overlay_lines.len()) as u64;
+        if self.vertex_buffer.size() < required * VERTEX_SIZE {
+            self.vertex_buffer = Self::create_vertex_buffer(device, required.next_power_of_two());
+        }
+        stats.write(
+            queue,
+            &self.vertex_buffer,
+            0,
+            bytemuck::cast_slice(&self.lines),
+        );
+        stats.write(
+            queue,
+            &self.vertex_buffer,
+            self.lines.len() as u64 * VERTEX_SIZE,
+            bytemuck::cast_slice(&self.overlay_lines),
         );
+    }
 
-        Self { color_lines, axis }
+    pub fn draw<'a: 'b, 'b>(
+        &'a self,
+        pass: &'b mut wgpu::RenderPass<'a>,
+        camera: &'a wgpu::BindGroup,
+        stats: &mut FrameStats,
+    ) {
+        let tested_end = self.lines.len() as u32;
+        let overlay_end = tested_end + self.overlay_lines.len() as u32;
+
+        pass.set_bind_group(0, camera, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        if tested_end > 0 {
+            pass.set_pipeline(&self.depth_tested);
+            pass.draw(0..tested_end, 0..1);
+            stats.pipeline();
+            stats.draw(0);
+        }
+        if overlay_end > tested_end {
+            pass.set_pipeline(&self.overlay);
+            pass.draw(tested_end..overlay_end, 0..1);
+            stats.pipeline();
+            stats.draw(0);
+        }
+        self.draw_axis(pass, camera);
+        stats.pipeline();
+        stats.draw(0);
+        self.text.draw(pass, stats);
     }
 
     pub fn draw_axis<'a: 'b, 'b>(
@@ -86,9 +344,15 @@ impl Debug {
         pass: &'b mut wgpu::RenderPass<'a>,
         camera: &'a wgpu::BindGroup,
     ) {
-        pass.set_pipeline(&self.color_lines);
+        pass.set_pipeline(&self.overlay);
         pass.set_bind_group(0, camera, &[]);
         pass.set_vertex_buffer(0, self.axis.slice(..));
         pass.draw(0..AXIS_COLORS.len() as u32, 0..1);
     }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.overlay_lines.clear();
+        self.text.clear();
+    }
 }
@@ -0,0 +1,233 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use glam::Mat4;
+
+use crate::{
+    game_object::{AlphaMode, DrawDepth, InstanceRaw, Mesh, Model, ModelVertex, Vertex},
+    instance::InstanceBuffer,
+    stats::FrameStats,
+    texture::Texture,
+};
+
+// Depth-only variants used by the shadow pass and the depth prepass. Like the
+// main pass they are indexed by whether the material is double-sided, masked
+// materials run an alpha test so cutouts don't write solid depth
+pub(crate) struct DepthPipelines {
+    opaque: [wgpu::RenderPipeline; 2],
+    masked: [wgpu::RenderPipeline; 2],
+}
+
+impl DepthPipelines {
+    pub fn new(
+        device: &wgpu::Device,
+        label: &str,
+        view_layout: &wgpu::BindGroupLayout,
+        material_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/shadow.wgsl"));
+        let opaque_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format!("{} Pipeline Layout", label)),
+            bind_group_layouts: &[view_layout],
+            push_constant_ranges: &[],
+        });
+        let masked_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format!("{} Masked Pipeline Layout", label)),
+            bind_group_layouts: &[view_layout, material_layout],
+            push_constant_ranges: &[],
+        });
+
+        let create = |masked: bool, cull_mode| {
+            let (layout, entry_point, fragment) = if masked {
+                let fragment = wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_masked",
+                    targets: &[],
+                };
+                (&masked_layout, "vs_masked", Some(fragment))
+            } else {
+                (&opaque_layout, "vs_main", None)
+            };
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(&format!("{} Pipeline", label)),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point,
+                    buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+                },
+                fragment,
+                primitive: wgpu::PrimitiveState {
+                    cull_mode,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let variants = |masked| [create(masked, Some(wgpu::Face::Back)), create(masked, None)];
+
+        Self {
+            opaque: variants(false),
+            masked: variants(true),
+        }
+    }
+
+    // Switches pipelines only when the variant changes, `bound` tracks the
+    // current one across calls within a pass
+    pub fn draw_mesh<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        model: &'a Model,
+        mesh: &'a Mesh,
+        instances: Range<u32>,
+        bound: &mut Option<(bool, bool)>,
+        stats: &mut FrameStats,
+    ) {
+        let factors = model.factors(mesh);
+        let material = model
+            .materials
+            .get(mesh.material)
+            .filter(|_| factors.alpha_mode == AlphaMode::Mask);
+        let variant = (material.is_some(), factors.double_sided);
+        if *bound != Some(variant) {
+            let variants = if variant.0 {
+                &self.masked
+            } else {
+                &self.opaque
+            };
+            pass.set_pipeline(&variants[variant.1 as usize]);
+            stats.pipeline();
+            *bound = Some(variant);
+        }
+
+        stats.draw(mesh.triangles() as u64 * instances.len() as u64);
+        match material {
+            Some(material) => pass.draw_depth_mesh_masked(mesh, material, instances),
+            None => pass.draw_depth_mesh_instanced(mesh, instances),
+        }
+    }
+}
+
+pub struct DepthPrepass {
+    pipelines: DepthPipelines,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    texture: Texture,
+}
+
+impl DepthPrepass {
+    pub fn new(
+        device: &wgpu::Device,
+        material_layout: &wgpu::BindGroupLayout,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("depth_prepass_bind_group_layout"),
+        });
+        let pipelines = DepthPipelines::new(device, "Depth Prepass", &layout, material_layout);
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Depth Prepass Buffer"),
+            size: std::mem::size_of::<Mat4>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("depth_prepass_bind_group"),
+        });
+
+        let texture = Texture::create_depth_texture(device, width, height, 1, "scene_depth");
+
+        Self {
+            pipelines,
+            uniform_buffer,
+            bind_group,
+            texture,
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.texture = Texture::create_depth_texture(device, width, height, 1, "scene_depth");
+    }
+
+    pub fn process<'a>(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        view_proj: Mat4,
+        instance_buffer: &InstanceBuffer,
+        objects: impl IntoIterator<Item = &'a (Arc<Model>, Range<usize>, Range<u32>)>,
+        stats: &mut FrameStats,
+    ) {
+        stats.write(
+            queue,
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&view_proj.to_cols_array()),
+        );
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("depth_prepass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        let mut bound = None;
+        for (model, meshes, range) in objects {
+            for (buffer, range) in instance_buffer.draws(range.clone()) {
+                pass.set_vertex_buffer(1, buffer.slice(..));
+                // match the main pass depth, which blended meshes don't write to
+                for mesh in model.meshes[meshes.clone()]
+                    .iter()
+                    .filter(|mesh| !model.is_blended(mesh))
+                {
+                    self.pipelines.draw_mesh(
+                        &mut pass,
+                        model,
+                        mesh,
+                        range.clone(),
+                        &mut bound,
+                        stats,
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.texture.view
+    }
+}
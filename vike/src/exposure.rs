@@ -0,0 +1,251 @@
+use web_time::Instant;
+use wgpu::util::DeviceExt;
+
+use crate::stats::FrameStats;
+
+#[derive(Clone, Debug)]
+pub struct AutoExposureSettings {
+    pub min_ev: f32,
+    pub max_ev: f32,
+    pub speed: f32,
+    pub compensation: f32,
+}
+
+impl Default for AutoExposureSettings {
+    fn default() -> Self {
+        Self {
+            min_ev: -8.0,
+            max_ev: 8.0,
+            speed: 1.5,
+            compensation: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct ExposureParams {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    compensation: f32,
+    num_pixels: u32,
+    _padding: [u32; 3],
+}
+
+pub struct AutoExposure {
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    params_buffer: wgpu::Buffer,
+    histogram_buffer: wgpu::Buffer,
+    state_buffer: wgpu::Buffer,
+    settings: AutoExposureSettings,
+    last_update: Option<Instant>,
+}
+
+impl AutoExposure {
+    pub fn is_supported(device: &wgpu::Device) -> bool {
+        let limits = device.limits();
+        limits.max_storage_buffers_per_shader_stage >= 2
+            && limits.max_compute_invocations_per_workgroup >= 256
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        hdr_view: &wgpu::TextureView,
+        settings: AutoExposureSettings,
+    ) -> Self {
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(2),
+                storage_entry(3),
+            ],
+            label: Some("exposure_bind_group_layout"),
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/exposure.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Exposure Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+        let histogram_pipeline = create_pipeline("build_histogram");
+        let average_pipeline = create_pipeline("average_histogram");
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Exposure Params Buffer"),
+            size: std::mem::size_of::<ExposureParams>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let histogram_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Luminance Histogram Buffer"),
+            size: 256 * 4,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let state_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Exposure State Buffer"),
+            contents: bytemuck::cast_slice(&[1.0f32, 0.0]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let bind_group = Self::create_bind_group(
+            device,
+            &layout,
+            hdr_view,
+            &params_buffer,
+            &histogram_buffer,
+            &state_buffer,
+        );
+
+        Self {
+            histogram_pipeline,
+            average_pipeline,
+            layout,
+            bind_group,
+            params_buffer,
+            histogram_buffer,
+            state_buffer,
+            settings,
+            last_update: None,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        hdr_view: &wgpu::TextureView,
+        params_buffer: &wgpu::Buffer,
+        histogram_buffer: &wgpu::Buffer,
+        state_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(hdr_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: state_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("exposure_bind_group"),
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, hdr_view: &wgpu::TextureView) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.layout,
+            hdr_view,
+            &self.params_buffer,
+            &self.histogram_buffer,
+            &self.state_buffer,
+        );
+    }
+
+    pub fn settings(&self) -> &AutoExposureSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: AutoExposureSettings) {
+        self.settings = settings;
+    }
+
+    pub fn process(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        (width, height): (u32, u32),
+        exposure_buffer: &wgpu::Buffer,
+        stats: &mut FrameStats,
+    ) {
+        let now = Instant::now();
+        let dt = self
+            .last_update
+            .map_or(0.0, |last| (now - last).as_secs_f32());
+        self.last_update = Some(now);
+
+        let params = ExposureParams {
+            min_log_luminance: self.settings.min_ev,
+            log_luminance_range: (self.settings.max_ev - self.settings.min_ev).max(f32::EPSILON),
+            adaptation: 1.0 - (-dt * self.settings.speed).exp(),
+            compensation: self.settings.compensation,
+            num_pixels: width * height,
+            _padding: [0; 3],
+        };
+        stats.write(
+            queue,
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&[params]),
+        );
+
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("exposure_compute_pass"),
+                timestamp_writes: None,
+            });
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.set_pipeline(&self.histogram_pipeline);
+            pass.dispatch_workgroups(width.div_ceil(16), height.div_ceil(16), 1);
+            pass.set_pipeline(&self.average_pipeline);
+            pass.dispatch_workgroups(1, 1, 1);
+            stats.pipeline();
+            stats.pipeline();
+        }
+
+        encoder.copy_buffer_to_buffer(&self.state_buffer, 0, exposure_buffer, 0, 4);
+    }
+}
@@ -5,15 +5,16 @@ use std::sync::Arc;
 
 use anyhow::Result;
 use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
 
 pub use glam::*;
 
-use crate::MAX_LIGHTS;
+use crate::assets::{self, AssetSource};
+use crate::camera::Frustum;
 use crate::renderer::Renderer;
-use crate::resources::load_model;
+use crate::resources::{ImportSettings, load_model};
 use crate::texture::Texture;
 
-#[derive(Default)]
 pub struct GameObjectStore {
     objects: BTreeMap<String, GameObject>,
     lights: BTreeMap<String, GameLight>,
@@ -21,13 +22,42 @@ pub struct GameObjectStore {
     models_to_objects: BTreeMap<String, Vec<String>>,
     models_to_lights: BTreeMap<String, Vec<String>>,
     targets_to_arrays: HashMap<String, BTreeMap<String, Array>>,
+    import_settings: ImportSettings,
+    assets: Box<dyn AssetSource>,
+}
+
+impl Default for GameObjectStore {
+    fn default() -> Self {
+        Self {
+            objects: BTreeMap::new(),
+            lights: BTreeMap::new(),
+            models: HashMap::new(),
+            models_to_objects: BTreeMap::new(),
+            models_to_lights: BTreeMap::new(),
+            targets_to_arrays: HashMap::new(),
+            import_settings: ImportSettings::default(),
+            assets: assets::default_source(),
+        }
+    }
 }
 
+// Draws are (model, mesh range, instance range), one per model part
 pub struct PreFrameData {
-    pub light_uniform: LightUniform,
-    pub objects: Vec<(Arc<Model>, Range<u32>)>,
-    pub lights: Vec<(Arc<Model>, Range<u32>)>,
+    pub light_data: Vec<Light>,
+    pub objects: Vec<(Arc<Model>, Range<usize>, Range<u32>)>,
+    pub lights: Vec<(Arc<Model>, Range<usize>, Range<u32>)>,
+    pub shadow_objects: Vec<(Arc<Model>, Range<usize>, Range<u32>)>,
+    pub transparent: Vec<(Arc<Model>, usize, Range<u32>)>,
     pub instances: Vec<InstanceRaw>,
+    pub shadow_lights: Vec<Vec3>,
+    pub culling: CullingStats,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CullingStats {
+    pub instances: u32,
+    pub visible: u32,
+    pub shadow_only: u32,
 }
 
 pub struct Array {
@@ -42,12 +72,37 @@ impl GameObjectStore {
         if let Some(model) = self.models.get(filename) {
             Ok(model.clone())
         } else {
-            let model = Arc::new(load_model(filename, renderer).await?);
+            let model = Arc::new(
+                load_model(
+                    filename,
+                    renderer,
+                    &self.import_settings,
+                    self.assets.as_ref(),
+                )
+                .await?,
+            );
             self.models.insert(filename.to_string(), model.clone());
             Ok(model)
         }
     }
 
+    pub fn import_settings(&self) -> &ImportSettings {
+        &self.import_settings
+    }
+
+    pub fn set_import_settings(&mut self, settings: ImportSettings) {
+        self.import_settings = settings;
+    }
+
+    pub fn asset_source(&self) -> &dyn AssetSource {
+        self.assets.as_ref()
+    }
+
+    // Models already loaded stay cached under their old paths
+    pub fn set_asset_source(&mut self, assets: impl AssetSource + 'static) {
+        self.assets = Box::new(assets);
+    }
+
     pub fn new_game_object(
         &mut self,
         name: &str,
@@ -74,6 +129,7 @@ impl GameObjectStore {
         model: Option<Arc<Model>>,
         color: Vec3,
         intensity: f32,
+        kind: LightKind,
     ) {
         let light = GameLight {
             name: name.to_string(),
@@ -81,6 +137,9 @@ impl GameObjectStore {
             model: model.clone(),
             color,
             intensity,
+            kind,
+            shadows: false,
+            range: None,
         };
 
         self.lights.insert(name.to_string(), light);
@@ -162,77 +221,162 @@ impl GameObjectStore {
         out
     }
 
-    pub fn pre_frame(&self) -> PreFrameData {
-        let mut light_uniform = LightUniform::default();
-        let mut index = 0;
+    pub fn pre_frame(&self, frustum: &Frustum, view_position: Vec3) -> PreFrameData {
+        let mut light_data = Vec::new();
+        let mut shadow_lights = Vec::new();
+        let mut shadow_bounds = Vec::new();
         for light in self.lights.values() {
             for transform in self.eval_array(&light.name, light.transform.clone()) {
-                if index >= MAX_LIGHTS {
-                    break;
-                }
-                light_uniform.lights[index] = Light {
-                    position: transform.position.into(),
-                    color: light.color.into(),
-                    intensity: light.intensity,
-                    _padding: 0,
+                let casts_shadows =
+                    light.shadows && !matches!(light.kind, LightKind::Directional { .. });
+                let shadow_index = if casts_shadows {
+                    shadow_lights.push(transform.position);
+                    shadow_bounds.push((transform.position, light.range()));
+                    shadow_lights.len() as i32 - 1
+                } else {
+                    -1
                 };
-                index += 1;
+                light_data.push(Light::new(light, &transform, shadow_index));
             }
         }
 
-        light_uniform.num_lights = std::cmp::max(index + 1, MAX_LIGHTS) as u32;
-
+        let mut culling = CullingStats::default();
         let mut instances = Vec::new();
         let mut object_models = Vec::new();
         let mut light_models = Vec::new();
+        let mut shadow_only = Vec::new();
+        let mut blended = Vec::new();
 
-        let (mut start, mut end) = (0, 0);
-        let mut curr_model = Arc::new(Model::default());
         for (model_name, object_names) in &self.models_to_objects {
+            let model = self.models.get(model_name).unwrap();
+            let parts = model.parts();
+            let blended_meshes = parts
+                .iter()
+                .map(|part| {
+                    model
+                        .blended_meshes()
+                        .filter(|mesh| part.meshes.contains(mesh))
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            let mut visible = vec![Vec::new(); parts.len()];
+            let mut casters = vec![Vec::new(); parts.len()];
             for object_name in object_names {
                 let object = self.objects.get(object_name).unwrap();
                 for transform in self.eval_array(object_name, object.transform.clone()) {
-                    instances.push(transform.to_raw_instance());
-                    if &curr_model.name == model_name {
-                        end += 1;
-                    } else {
-                        if start != end {
-                            object_models.push((curr_model, start..end));
+                    let (matrix, normal) = (transform.model(), transform.normal());
+                    let (mut seen, mut casts) = (false, false);
+                    for (i, part) in parts.iter().enumerate() {
+                        let matrix = matrix * part.transform;
+                        let bounds = part.bounds.transform(&matrix);
+                        let instance = InstanceRaw::new(matrix, normal * part.normal());
+                        if frustum.intersects_aabb(&bounds) {
+                            seen = true;
+                            for &mesh in &blended_meshes[i] {
+                                let center =
+                                    matrix.transform_point3(model.meshes[mesh].bounds.center());
+                                let distance = center.distance_squared(view_position);
+                                blended.push((distance, model, mesh, instance));
+                            }
+                            visible[i].push(instance);
+                        } else if shadow_bounds
+                            .iter()
+                            .any(|(position, range)| bounds.intersects_sphere(*position, *range))
+                        {
+                            casts = true;
+                            casters[i].push(instance);
                         }
-                        curr_model = self.models.get(model_name).unwrap().clone();
-                        (start, end) = (end, end + 1);
                     }
+                    culling.instances += 1;
+                    if seen {
+                        culling.visible += 1;
+                    } else if casts {
+                        culling.shadow_only += 1;
+                    }
+                }
+            }
+            for ((part, visible), casters) in parts.iter().zip(visible).zip(casters) {
+                if !visible.is_empty() {
+                    let start = instances.len() as u32;
+                    instances.extend(visible);
+                    object_models.push((
+                        model.clone(),
+                        part.meshes.clone(),
+                        start..instances.len() as u32,
+                    ));
+                }
+                if !casters.is_empty() {
+                    shadow_only.push((model.clone(), part.meshes.clone(), casters));
                 }
             }
         }
-        object_models.push((curr_model.clone(), start..end));
-        start = end;
         for (model_name, light_names) in &self.models_to_lights {
+            let model = self.models.get(model_name).unwrap();
+            let parts = model.parts();
+            let mut visible = vec![Vec::new(); parts.len()];
             for light_name in light_names {
                 let light = self.lights.get(light_name).unwrap();
                 for transform in self.eval_array(light_name, light.transform.clone()) {
-                    let mut instance = transform.to_raw_instance();
-                    instance.normal[0] = light.color.into();
-                    instances.push(instance);
-                    if &curr_model.name == model_name {
-                        end += 1;
-                    } else {
-                        if start != end {
-                            light_models.push((curr_model, start..end));
+                    let mut seen = false;
+                    for (i, part) in parts.iter().enumerate() {
+                        let matrix = transform.model() * part.transform;
+                        if !frustum.intersects_aabb(&part.bounds.transform(&matrix)) {
+                            continue;
                         }
-                        curr_model = self.models.get(model_name).unwrap().clone();
-                        (start, end) = (end, end + 1);
+                        seen = true;
+                        // the light shader reads its color from the normal matrix
+                        let mut instance = InstanceRaw::new(matrix, Mat3::IDENTITY);
+                        instance.normal[0] = light.color.into();
+                        visible[i].push(instance);
                     }
+                    culling.instances += 1;
+                    culling.visible += seen as u32;
                 }
             }
+            for (part, visible) in parts.iter().zip(visible) {
+                if !visible.is_empty() {
+                    let start = instances.len() as u32;
+                    instances.extend(visible);
+                    light_models.push((
+                        model.clone(),
+                        part.meshes.clone(),
+                        start..instances.len() as u32,
+                    ));
+                }
+            }
+        }
+
+        blended.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let mut transparent: Vec<(Arc<Model>, usize, Range<u32>)> = Vec::new();
+        for (_, model, mesh, instance) in blended {
+            let index = instances.len() as u32;
+            instances.push(instance);
+            match transparent.last_mut() {
+                Some((last, last_mesh, range))
+                    if Arc::ptr_eq(last, model) && *last_mesh == mesh =>
+                {
+                    range.end = index + 1;
+                }
+                _ => transparent.push((model.clone(), mesh, index..index + 1)),
+            }
+        }
+
+        let mut shadow_objects = object_models.clone();
+        for (model, meshes, casters) in shadow_only {
+            let start = instances.len() as u32;
+            instances.extend(casters);
+            shadow_objects.push((model, meshes, start..instances.len() as u32));
         }
-        light_models.push((curr_model.clone(), start..end));
 
         PreFrameData {
-            light_uniform,
+            light_data,
             objects: object_models,
             lights: light_models,
+            shadow_objects,
+            transparent,
             instances,
+            shadow_lights,
+            culling,
         }
     }
 
@@ -273,6 +417,43 @@ pub struct GameLight {
     pub model: Option<Arc<Model>>,
     pub color: Vec3,
     pub intensity: f32,
+    pub kind: LightKind,
+    pub shadows: bool,
+    pub range: Option<f32>,
+}
+
+impl GameLight {
+    pub const CUTOFF: f32 = 0.01;
+
+    pub fn range(&self) -> f32 {
+        match self.kind {
+            LightKind::Directional { .. } => f32::MAX,
+            _ => self
+                .range
+                .unwrap_or_else(|| (self.intensity / Self::CUTOFF).sqrt()),
+        }
+    }
+}
+
+// Directions are in the light's local space and follow its transform's
+// rotation, so arrays with rotated offsets point each light differently
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    Point,
+    Directional {
+        direction: Vec3,
+    },
+    Spot {
+        direction: Vec3,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+impl LightKind {
+    pub const POINT: u32 = 0;
+    pub const DIRECTIONAL: u32 = 1;
+    pub const SPOT: u32 = 2;
 }
 
 #[derive(Clone, Debug)]
@@ -289,6 +470,15 @@ pub struct InstanceRaw {
     normal: [[f32; 3]; 3],
 }
 
+impl InstanceRaw {
+    pub fn new(model: Mat4, normal: Mat3) -> Self {
+        Self {
+            model: model.to_cols_array_2d(),
+            normal: normal.to_cols_array_2d(),
+        }
+    }
+}
+
 #[rustfmt::skip]
 impl Transform3D {
     pub fn model(&self) -> Mat4 {
@@ -346,11 +536,20 @@ impl Transform3D {
         ])
     }
 
+    // The rotation part of model(), without scale or translation
+    pub fn rotation_matrix(&self) -> Mat3 {
+        Mat3::from_mat4(
+            Transform3D {
+                position: Vec3::ZERO,
+                rotation: self.rotation,
+                scale: Vec3::ONE,
+            }
+            .model(),
+        )
+    }
+
     pub fn to_raw_instance(&self) -> InstanceRaw {
-        InstanceRaw {
-            model: self.model().to_cols_array_2d(),
-            normal: self.normal().to_cols_array_2d(),
-        }
+        InstanceRaw::new(self.model(), self.normal())
     }
 }
 
@@ -386,38 +585,158 @@ pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
-    pub tangent: [f32; 3],
-    pub bitangent: [f32; 3],
+    // xyz along +u, w is the handedness of the bitangent cross(normal, tangent) * w
+    pub tangent: [f32; 4],
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Zeroable, Pod)]
 pub struct Light {
     pub position: [f32; 3],
-    _padding: u32,
+    pub shadow_index: i32,
     pub color: [f32; 3],
     pub intensity: f32,
+    pub direction: [f32; 3],
+    pub kind: u32,
+    pub inner_cos: f32,
+    pub outer_cos: f32,
+    pub range: f32,
+    _padding: u32,
+}
+
+impl Light {
+    pub fn new(light: &GameLight, transform: &Transform3D, shadow_index: i32) -> Self {
+        let (kind, direction, inner_cos, outer_cos) = match light.kind {
+            LightKind::Point => (LightKind::POINT, Vec3::NEG_Y, -1.0, -1.0),
+            LightKind::Directional { direction } => (LightKind::DIRECTIONAL, direction, -1.0, -1.0),
+            LightKind::Spot {
+                direction,
+                inner_angle,
+                outer_angle,
+            } => (
+                LightKind::SPOT,
+                direction,
+                inner_angle.cos(),
+                outer_angle.cos(),
+            ),
+        };
+
+        Self {
+            position: transform.position.into(),
+            shadow_index,
+            color: light.color.into(),
+            intensity: light.intensity,
+            direction: (transform.rotation_matrix() * direction)
+                .normalize_or_zero()
+                .into(),
+            kind,
+            inner_cos,
+            outer_cos,
+            range: light.range(),
+            _padding: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum AlphaMode {
+    #[default]
+    Opaque,
+    Mask,
+    Blend,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MaterialFactors {
+    pub base_color: Vec4,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub specular: f32,
+    pub emissive: Vec3,
+    pub occlusion_strength: f32,
+    pub normal_scale: f32,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+}
+
+impl Default for MaterialFactors {
+    fn default() -> Self {
+        Self {
+            base_color: Vec4::ONE,
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            emissive: Vec3::ZERO,
+            occlusion_strength: 1.0,
+            normal_scale: 1.0,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+        }
+    }
+}
+
+// Textures can be shared between the materials of a model
+pub struct MaterialTextures {
+    pub base_color: Arc<Texture>,
+    pub metallic_roughness: Arc<Texture>,
+    pub normal: Arc<Texture>,
+    pub emissive: Arc<Texture>,
+    pub occlusion: Arc<Texture>,
+}
+
+impl MaterialTextures {
+    pub fn default(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self {
+            base_color: Texture::from_color([255; 4], true, device, queue).into(),
+            metallic_roughness: Texture::from_color([255; 4], false, device, queue).into(),
+            normal: Texture::from_color([128, 128, 255, 255], false, device, queue).into(),
+            emissive: Texture::from_color([255; 4], true, device, queue).into(),
+            occlusion: Texture::from_color([255; 4], false, device, queue).into(),
+        }
+    }
+
+    fn iter(&self) -> [&Texture; 5] {
+        [
+            &self.base_color,
+            &self.metallic_roughness,
+            &self.normal,
+            &self.emissive,
+            &self.occlusion,
+        ]
+    }
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Zeroable, Pod)]
-pub struct LightUniform {
-    pub num_lights: u32,
-    _padding: [u32; 3],
-    pub lights: [Light; MAX_LIGHTS],
+pub struct MaterialUniform {
+    base_color: [f32; 4],
+    emissive: [f32; 3],
+    metallic: f32,
+    roughness: f32,
+    specular: f32,
+    occlusion_strength: f32,
+    normal_scale: f32,
+    alpha_cutoff: f32,
+    _padding: [f32; 3],
 }
 
-impl Default for LightUniform {
-    fn default() -> Self {
+impl From<&MaterialFactors> for MaterialUniform {
+    fn from(factors: &MaterialFactors) -> Self {
         Self {
-            num_lights: 0,
-            _padding: [0; 3],
-            lights: [Light {
-                position: [0.0; 3],
-                _padding: 0,
-                color: [0.0; 3],
-                intensity: 0.0,
-            }; MAX_LIGHTS],
+            base_color: factors.base_color.into(),
+            emissive: factors.emissive.into(),
+            metallic: factors.metallic,
+            roughness: factors.roughness,
+            specular: factors.specular,
+            occlusion_strength: factors.occlusion_strength,
+            normal_scale: factors.normal_scale,
+            alpha_cutoff: match factors.alpha_mode {
+                AlphaMode::Mask => factors.alpha_cutoff,
+                _ => 0.0,
+            },
+            _padding: [0.0; 3],
         }
     }
 }
@@ -425,18 +744,89 @@ impl Default for LightUniform {
 #[allow(dead_code)]
 pub struct Material {
     pub name: String,
-    diffuse_texture: Texture,
-    normal_texture: Texture,
+    pub factors: MaterialFactors,
+    textures: MaterialTextures,
+    uniform_buffer: wgpu::Buffer,
     bind_group: wgpu::BindGroup,
 }
 
-#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        let mut points = points.into_iter();
+        let Some(first) = points.next() else {
+            return Self::default();
+        };
+
+        points.fold(Self::new(first, first), |aabb, point| {
+            Self::new(aabb.min.min(point), aabb.max.max(point))
+        })
+    }
+
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self::new(self.min.min(other.min), self.max.max(other.max))
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn transform(&self, matrix: &Mat4) -> Self {
+        let center = matrix.transform_point3(self.center());
+        let extents = Mat3::from_mat4(*matrix).abs() * self.extents();
+        Self::new(center - extents, center + extents)
+    }
+
+    pub fn intersects_sphere(&self, center: Vec3, radius: f32) -> bool {
+        center.clamp(self.min, self.max).distance_squared(center) <= radius * radius
+    }
+}
+
 pub struct Mesh {
     pub name: String,
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
     num_elements: u32,
     pub material: usize,
+    pub bounds: Aabb,
+}
+
+// `transform` is relative to the parent, which always comes earlier in
+// `Model::nodes`. Nodes that instance the same mesh share a mesh range
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub name: String,
+    pub transform: Mat4,
+    pub parent: Option<usize>,
+    pub meshes: Range<usize>,
+}
+
+// A mesh range drawn with its own transform into model space. `bounds` are
+// in mesh space
+#[derive(Clone, Debug)]
+pub struct ModelPart {
+    pub transform: Mat4,
+    pub meshes: Range<usize>,
+    pub bounds: Aabb,
+}
+
+impl ModelPart {
+    pub fn normal(&self) -> Mat3 {
+        Mat3::from_mat4(self.transform).inverse().transpose()
+    }
 }
 
 #[derive(Default)]
@@ -444,6 +834,8 @@ pub struct Model {
     pub name: String,
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    pub nodes: Vec<Node>,
+    pub bounds: Aabb,
 }
 
 unsafe impl Send for Model {}
@@ -456,6 +848,7 @@ impl Mesh {
         index_buffer: wgpu::Buffer,
         num_elements: u32,
         material: usize,
+        bounds: Aabb,
     ) -> Self {
         Self {
             name: String::from(name),
@@ -463,45 +856,58 @@ impl Mesh {
             index_buffer,
             num_elements,
             material,
+            bounds,
         }
     }
+
+    pub fn triangles(&self) -> u32 {
+        self.num_elements / 3
+    }
 }
 
 impl Material {
+    pub const NUM_TEXTURES: u32 = 5;
+
     pub fn new(
         device: &wgpu::Device,
         name: &str,
-        diffuse_texture: Texture,
-        normal_texture: Texture,
+        textures: MaterialTextures,
+        factors: MaterialFactors,
         layout: &wgpu::BindGroupLayout,
     ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Material Buffer", name)),
+            contents: bytemuck::cast_slice(&[MaterialUniform::from(&factors)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let mut entries = Vec::new();
+        for (i, texture) in textures.iter().into_iter().enumerate() {
+            entries.push(wgpu::BindGroupEntry {
+                binding: i as u32 * 2,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: i as u32 * 2 + 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            });
+        }
+        entries.push(wgpu::BindGroupEntry {
+            binding: Self::NUM_TEXTURES * 2,
+            resource: uniform_buffer.as_entire_binding(),
+        });
+
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
-                },
-            ],
+            entries: &entries,
             label: Some(name),
         });
 
         Self {
             name: String::from(name),
-            diffuse_texture,
-            normal_texture,
+            factors,
+            textures,
+            uniform_buffer,
             bind_group,
         }
     }
@@ -509,11 +915,67 @@ impl Material {
 
 impl Model {
     pub fn new(name: &str, meshes: Vec<Mesh>, materials: Vec<Material>) -> Self {
-        Self {
+        let mut model = Self {
             name: name.to_string(),
             meshes,
             materials,
+            nodes: Vec::new(),
+            bounds: Aabb::default(),
+        };
+        model.update_bounds();
+        model
+    }
+
+    // Models without nodes are a single part with every mesh
+    pub fn parts(&self) -> Vec<ModelPart> {
+        let part = |transform, meshes: Range<usize>| ModelPart {
+            transform,
+            bounds: self.meshes[meshes.clone()]
+                .iter()
+                .map(|mesh| mesh.bounds)
+                .reduce(|a, b| a.union(&b))
+                .unwrap_or_default(),
+            meshes,
+        };
+        if self.nodes.is_empty() {
+            return vec![part(Mat4::IDENTITY, 0..self.meshes.len())];
+        }
+
+        let mut worlds: Vec<Mat4> = Vec::with_capacity(self.nodes.len());
+        let mut parts = Vec::new();
+        for node in &self.nodes {
+            let parent = node.parent.map_or(Mat4::IDENTITY, |parent| worlds[parent]);
+            worlds.push(parent * node.transform);
+            if !node.meshes.is_empty() {
+                parts.push(part(parent * node.transform, node.meshes.clone()));
+            }
         }
+        parts
+    }
+
+    // Call after changing `nodes` so culling uses the new extents
+    pub fn update_bounds(&mut self) {
+        self.bounds = self
+            .parts()
+            .iter()
+            .map(|part| part.bounds.transform(&part.transform))
+            .reduce(|a, b| a.union(&b))
+            .unwrap_or_default();
+    }
+
+    pub fn factors(&self, mesh: &Mesh) -> MaterialFactors {
+        self.materials
+            .get(mesh.material)
+            .map(|material| material.factors)
+            .unwrap_or_default()
+    }
+
+    pub fn is_blended(&self, mesh: &Mesh) -> bool {
+        self.factors(mesh).alpha_mode == AlphaMode::Blend
+    }
+
+    pub fn blended_meshes(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.meshes.len()).filter(|&i| self.is_blended(&self.meshes[i]))
     }
 }
 
@@ -720,6 +1182,44 @@ where
     }
 }
 
+pub trait DrawDepth<'a> {
+    fn draw_depth_mesh_instanced(&mut self, mesh: &'a Mesh, instances: Range<u32>);
+    fn draw_depth_mesh_masked(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        instances: Range<u32>,
+    );
+    fn draw_depth_model_instanced(&mut self, model: &'a Model, instances: Range<u32>);
+}
+
+impl<'a, 'b> DrawDepth<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_depth_mesh_instanced(&mut self, mesh: &'b Mesh, instances: Range<u32>) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.draw_indexed(0..mesh.num_elements, 0, instances);
+    }
+
+    fn draw_depth_mesh_masked(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        instances: Range<u32>,
+    ) {
+        self.set_bind_group(1, &material.bind_group, &[]);
+        self.draw_depth_mesh_instanced(mesh, instances);
+    }
+
+    fn draw_depth_model_instanced(&mut self, model: &'b Model, instances: Range<u32>) {
+        for mesh in &model.meshes {
+            self.draw_depth_mesh_instanced(mesh, instances.clone());
+        }
+    }
+}
+
 pub trait Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static>;
 }
@@ -748,12 +1248,7 @@ impl Vertex for ModelVertex {
                 wgpu::VertexAttribute {
                     offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
                     shader_location: 3,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 11]>() as wgpu::BufferAddress,
-                    shader_location: 4,
-                    format: wgpu::VertexFormat::Float32x3,
+                    format: wgpu::VertexFormat::Float32x4,
                 },
             ],
         }
@@ -805,3 +1300,63 @@ impl Vertex for InstanceRaw {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transformed_aabb_contains_the_transformed_corners() {
+        let aabb = Aabb::new(Vec3::new(-1.0, 0.0, -2.0), Vec3::new(2.0, 1.0, 3.0));
+        let matrix = Transform3D {
+            position: Vec3::new(4.0, -2.0, 1.0),
+            rotation: Vec3::new(0.3, 1.1, -0.7),
+            scale: Vec3::new(2.0, 0.5, 3.0),
+        }
+        .model();
+        let bounds = aabb.transform(&matrix);
+
+        for i in 0..8 {
+            let corner = Vec3::select(
+                glam::BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0),
+                aabb.max,
+                aabb.min,
+            );
+            let point = matrix.transform_point3(corner);
+            assert!(
+                point.cmpge(bounds.min - 1e-4).all() && point.cmple(bounds.max + 1e-4).all(),
+                "{point} outside {bounds:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn light_direction_follows_rotation() {
+        let light = GameLight {
+            name: "spot".to_string(),
+            transform: Transform3D::default(),
+            model: None,
+            color: Vec3::ONE,
+            intensity: 1.0,
+            kind: LightKind::Spot {
+                direction: Vec3::NEG_Y,
+                inner_angle: 0.3,
+                outer_angle: 0.5,
+            },
+            shadows: false,
+            range: None,
+        };
+        let unrotated = Light::new(&light, &Transform3D::default(), -1);
+        assert!(Vec3::from(unrotated.direction).abs_diff_eq(Vec3::NEG_Y, 1e-6));
+
+        let transform = Transform3D {
+            rotation: Vec3::new(0.0, 0.0, std::f32::consts::FRAC_PI_2),
+            scale: Vec3::splat(3.0),
+            ..Default::default()
+        };
+        let rotated = Light::new(&light, &transform, -1);
+        let expected = transform.model().transform_vector3(Vec3::NEG_Y).normalize();
+        assert!(Vec3::from(rotated.direction).abs_diff_eq(expected, 1e-6));
+        assert!(!expected.abs_diff_eq(Vec3::NEG_Y, 1e-3));
+    }
+}
@@ -1,23 +1,91 @@
-use wgpu::Operations;
+use wgpu::{Operations, util::DeviceExt};
 
-use crate::{renderer::Renderer, texture::Texture};
+use crate::{
+    exposure::{AutoExposure, AutoExposureSettings},
+    renderer::Renderer,
+    stats::FrameStats,
+    texture::Texture,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tonemapper {
+    None,
+    Reinhard,
+    #[default]
+    Aces,
+    AgX,
+}
+
+#[derive(Clone, Debug)]
+pub struct ToneMapping {
+    pub tonemapper: Tonemapper,
+    pub exposure: f32,
+    // only used by Reinhard, the other operators have a fixed curve
+    pub white_point: f32,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            tonemapper: Tonemapper::default(),
+            exposure: 1.0,
+            white_point: 4.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct ToneMappingUniform {
+    exposure: f32,
+    white_point: f32,
+    tonemapper: u32,
+    _padding: u32,
+}
+
+// Debug views are shown as-is, without exposure or tone mapping
+const PASSTHROUGH: ToneMapping = ToneMapping {
+    tonemapper: Tonemapper::None,
+    exposure: 1.0,
+    white_point: 1.0,
+};
+
+impl From<&ToneMapping> for ToneMappingUniform {
+    fn from(tone_mapping: &ToneMapping) -> Self {
+        Self {
+            exposure: tone_mapping.exposure,
+            white_point: tone_mapping.white_point.max(f32::EPSILON),
+            tonemapper: tone_mapping.tonemapper as u32,
+            _padding: 0,
+        }
+    }
+}
 
 pub struct HdrPipeline {
     pipeline: wgpu::RenderPipeline,
     bind_group: wgpu::BindGroup,
     texture: Texture,
+    msaa_texture: Option<Texture>,
+    sample_count: u32,
     format: wgpu::TextureFormat,
     layout: wgpu::BindGroupLayout,
+    tone_mapping: ToneMapping,
+    uniform_buffer: wgpu::Buffer,
+    auto_exposure: Option<AutoExposure>,
+    passthrough: bool,
 }
 
 impl HdrPipeline {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
     pub fn new(
         device: &wgpu::Device,
         width: u32,
         height: u32,
+        sample_count: u32,
         pipeline_format: wgpu::TextureFormat,
     ) -> Self {
-        let format = wgpu::TextureFormat::Rgba16Float;
+        let format = Self::FORMAT;
 
         let texture = Texture::create_2d_texture(
             device,
@@ -47,23 +115,27 @@ impl HdrPipeline {
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
             label: Some("hdr_bind_group_layout"),
         });
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
-                },
-            ],
-            label: Some("hdr_bind_group"),
+
+        let tone_mapping = ToneMapping::default();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tone Mapping Buffer"),
+            contents: bytemuck::cast_slice(&[ToneMappingUniform::from(&tone_mapping)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
+        let bind_group = Self::create_bind_group(device, &layout, &texture, &uniform_buffer);
 
         let shader = wgpu::include_wgsl!("../shaders/hdr.wgsl");
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -77,47 +149,167 @@ impl HdrPipeline {
             &pipeline_layout,
             pipeline_format,
             None,
+            None,
             &[],
-            wgpu::PrimitiveTopology::TriangleList,
+            wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            1,
             shader,
         );
 
+        let msaa_texture = Self::create_msaa_texture(device, width, height, sample_count);
+
         Self {
             pipeline,
             bind_group,
             layout,
             texture,
+            msaa_texture,
+            sample_count,
             format,
+            tone_mapping,
+            uniform_buffer,
+            auto_exposure: None,
+            passthrough: false,
         }
     }
 
-    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
-        self.texture = Texture::create_2d_texture(
-            device,
-            width,
-            height,
-            wgpu::TextureFormat::Rgba16Float,
-            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
-            wgpu::FilterMode::Nearest,
-            Some("hdr texture"),
-        );
-        self.bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &self.layout,
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &Texture,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&self.texture.view),
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&self.texture.sampler),
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
                 },
             ],
             label: Some("hdr_bind_group"),
-        });
+        })
+    }
+
+    pub fn tone_mapping(&self) -> &ToneMapping {
+        &self.tone_mapping
+    }
+
+    pub fn set_tone_mapping(&mut self, queue: &wgpu::Queue, tone_mapping: ToneMapping) {
+        let uniform = if self.passthrough {
+            ToneMappingUniform::from(&PASSTHROUGH)
+        } else {
+            ToneMappingUniform::from(&tone_mapping)
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        self.tone_mapping = tone_mapping;
     }
 
-    pub fn process(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+    pub fn set_passthrough(&mut self, queue: &wgpu::Queue, passthrough: bool) {
+        self.passthrough = passthrough;
+        self.set_tone_mapping(queue, self.tone_mapping.clone());
+    }
+
+    fn create_msaa_texture(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> Option<Texture> {
+        (sample_count > 1).then(|| {
+            Texture::create_multisampled_texture(
+                device,
+                width,
+                height,
+                Self::FORMAT,
+                sample_count,
+                Some("hdr_msaa_texture"),
+            )
+        })
+    }
+
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        let size = self.texture.texture.size();
+        self.sample_count = sample_count;
+        self.msaa_texture =
+            Self::create_msaa_texture(device, size.width, size.height, sample_count);
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.texture = Texture::create_2d_texture(
+            device,
+            width,
+            height,
+            Self::FORMAT,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            wgpu::FilterMode::Nearest,
+            Some("hdr texture"),
+        );
+        self.msaa_texture = Self::create_msaa_texture(device, width, height, self.sample_count);
+        self.bind_group =
+            Self::create_bind_group(device, &self.layout, &self.texture, &self.uniform_buffer);
+        if let Some(auto_exposure) = &mut self.auto_exposure {
+            auto_exposure.resize(device, &self.texture.view);
+        }
+    }
+
+    pub fn auto_exposure(&self) -> Option<&AutoExposureSettings> {
+        self.auto_exposure
+            .as_ref()
+            .map(|auto_exposure| auto_exposure.settings())
+    }
+
+    pub fn set_auto_exposure(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        settings: Option<AutoExposureSettings>,
+    ) {
+        match (settings, &mut self.auto_exposure) {
+            (Some(settings), Some(auto_exposure)) => auto_exposure.set_settings(settings),
+            (Some(settings), None) if AutoExposure::is_supported(device) => {
+                self.auto_exposure = Some(AutoExposure::new(device, &self.texture.view, settings));
+            }
+            (Some(_), None) => log::warn!("auto exposure requires compute shader support"),
+            (None, _) => {
+                self.auto_exposure = None;
+                self.set_tone_mapping(queue, self.tone_mapping.clone());
+            }
+        }
+    }
+
+    pub fn process(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        output: &wgpu::TextureView,
+        stats: &mut FrameStats,
+    ) {
+        if let Some(auto_exposure) = &mut self.auto_exposure
+            && !self.passthrough
+        {
+            let size = self.texture.texture.size();
+            auto_exposure.process(
+                encoder,
+                queue,
+                (size.width, size.height),
+                &self.uniform_buffer,
+                stats,
+            );
+        }
+
         let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                 view: output,
@@ -135,12 +327,24 @@ impl HdrPipeline {
         pass.set_pipeline(&self.pipeline);
         pass.set_bind_group(0, &self.bind_group, &[]);
         pass.draw(0..3, 0..1);
+        stats.pipeline();
+        stats.draw(1);
     }
 
     pub fn view(&self) -> &wgpu::TextureView {
         &self.texture.view
     }
 
+    pub fn render_view(&self) -> &wgpu::TextureView {
+        self.msaa_texture
+            .as_ref()
+            .map_or(&self.texture.view, |texture| &texture.view)
+    }
+
+    pub fn resolve_target(&self) -> Option<&wgpu::TextureView> {
+        self.msaa_texture.as_ref().map(|_| &self.texture.view)
+    }
+
     pub fn format(&self) -> wgpu::TextureFormat {
         self.format
     }
@@ -0,0 +1,592 @@
+use std::sync::Arc;
+
+use wgpu::util::DeviceExt;
+
+use crate::{hdr::HdrPipeline, texture::Texture};
+
+const RADIANCE_SIZE: u32 = 256;
+const SPECULAR_MIPS: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 128;
+
+#[derive(Clone, Debug)]
+pub struct IblSettings {
+    pub intensity: f32,
+    pub irradiance_size: u32,
+    pub specular_size: u32,
+}
+
+impl Default for IblSettings {
+    fn default() -> Self {
+        Self {
+            intensity: 1.0,
+            irradiance_size: 32,
+            specular_size: 128,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct EnvironmentUniform {
+    intensity: f32,
+    max_lod: f32,
+    enabled: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct IblParams {
+    face: u32,
+    roughness: f32,
+    source_size: f32,
+    num_samples: u32,
+}
+
+struct IblGenerator {
+    downsample: wgpu::RenderPipeline,
+    irradiance: wgpu::RenderPipeline,
+    prefilter: wgpu::RenderPipeline,
+    brdf: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+}
+
+impl IblGenerator {
+    fn new(device: &wgpu::Device) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("ibl_generator_bind_group_layout"),
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/ibl.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("IBL Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: HdrPipeline::FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            downsample: create_pipeline("fs_downsample"),
+            irradiance: create_pipeline("fs_irradiance"),
+            prefilter: create_pipeline("fs_prefilter"),
+            brdf: create_pipeline("fs_brdf"),
+            layout,
+            sampler,
+        }
+    }
+
+    fn pass(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        source: &wgpu::TextureView,
+        params: IblParams,
+        target: &wgpu::TextureView,
+    ) {
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("IBL Params Buffer"),
+            contents: bytemuck::cast_slice(&[params]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: params_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("ibl_generator_bind_group"),
+        });
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("ibl_render_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    fn face_view(texture: &wgpu::Texture, face: u32, mip: u32) -> wgpu::TextureView {
+        texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_mip_level: mip,
+            mip_level_count: Some(1),
+            base_array_layer: face,
+            array_layer_count: Some(1),
+            ..Default::default()
+        })
+    }
+
+    fn mip_view(texture: &wgpu::Texture, mip: u32) -> wgpu::TextureView {
+        texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            base_mip_level: mip,
+            mip_level_count: Some(1),
+            ..Default::default()
+        })
+    }
+}
+
+pub struct Environment {
+    generator: IblGenerator,
+    layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    irradiance: Texture,
+    prefiltered: Texture,
+    brdf_lut: Texture,
+    white: Texture,
+    occlusion: wgpu::TextureView,
+    source: Option<Arc<Texture>>,
+    settings: IblSettings,
+}
+
+impl Environment {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, settings: IblSettings) -> Self {
+        let cube_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                cube_entry(0),
+                cube_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("environment_bind_group_layout"),
+        });
+
+        let generator = IblGenerator::new(device);
+
+        let brdf_lut = Texture::create_2d_texture(
+            device,
+            BRDF_LUT_SIZE,
+            BRDF_LUT_SIZE,
+            HdrPipeline::FORMAT,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            wgpu::FilterMode::Linear,
+            Some("brdf_lut"),
+        );
+        let placeholder = Self::placeholder(device, queue);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("BRDF LUT Encoder"),
+        });
+        generator.pass(
+            device,
+            &mut encoder,
+            &generator.brdf,
+            &placeholder.view,
+            IblParams {
+                num_samples: 256,
+                ..Default::default()
+            },
+            &brdf_lut.view,
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[EnvironmentUniform::default()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let irradiance = Self::placeholder(device, queue);
+        let prefiltered = Self::placeholder(device, queue);
+        let white = Texture::from_color([255; 4], false, device, queue);
+        let occlusion = white.texture.create_view(&Default::default());
+        let bind_group = Self::create_bind_group(
+            device,
+            &layout,
+            &irradiance,
+            &prefiltered,
+            &brdf_lut,
+            &occlusion,
+            &uniform_buffer,
+        );
+
+        Self {
+            generator,
+            layout,
+            uniform_buffer,
+            bind_group,
+            irradiance,
+            prefiltered,
+            brdf_lut,
+            white,
+            occlusion,
+            source: None,
+            settings,
+        }
+    }
+
+    fn placeholder(device: &wgpu::Device, queue: &wgpu::Queue) -> Texture {
+        let placeholder = Texture::create_cubemap(
+            device,
+            1,
+            1,
+            HdrPipeline::FORMAT,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            Some("environment_placeholder"),
+        );
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &placeholder.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &[0; 8 * 6],
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(8),
+                rows_per_image: Some(1),
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 6,
+            },
+        );
+        placeholder
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        irradiance: &Texture,
+        prefiltered: &Texture,
+        brdf_lut: &Texture,
+        occlusion: &wgpu::TextureView,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&irradiance.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&prefiltered.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&brdf_lut.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&prefiltered.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(occlusion),
+                },
+            ],
+            label: Some("environment_bind_group"),
+        })
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    pub fn set_occlusion(&mut self, device: &wgpu::Device, occlusion: Option<&wgpu::Texture>) {
+        self.occlusion = occlusion
+            .unwrap_or(&self.white.texture)
+            .create_view(&Default::default());
+        self.update_bind_group(device);
+    }
+
+    pub fn source(&self) -> Option<&Arc<Texture>> {
+        self.source.as_ref()
+    }
+
+    pub fn settings(&self) -> &IblSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        settings: IblSettings,
+    ) {
+        let regenerate = settings.irradiance_size != self.settings.irradiance_size
+            || settings.specular_size != self.settings.specular_size;
+        self.settings = settings;
+        if regenerate {
+            self.set_source(device, queue, self.source.clone());
+        } else {
+            self.write_uniform(queue);
+        }
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        let uniform = EnvironmentUniform {
+            intensity: self.settings.intensity,
+            max_lod: (self.prefiltered.texture.mip_level_count() - 1) as f32,
+            enabled: self.source.is_some() as u32,
+            _padding: 0,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    pub fn set_source(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        source: Option<Arc<Texture>>,
+    ) {
+        self.source = source;
+        let Some(source) = &self.source else {
+            self.irradiance = Self::placeholder(device, queue);
+            self.prefiltered = Self::placeholder(device, queue);
+            self.update_bind_group(device);
+            self.write_uniform(queue);
+            return;
+        };
+
+        let usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT;
+        let radiance_size = source.texture.width().clamp(1, RADIANCE_SIZE);
+        let radiance_mips = radiance_size.ilog2() + 1;
+        let radiance = Texture::create_cubemap(
+            device,
+            radiance_size,
+            radiance_mips,
+            HdrPipeline::FORMAT,
+            usage,
+            Some("environment_radiance"),
+        );
+        let irradiance_size = self.settings.irradiance_size.max(1);
+        let irradiance = Texture::create_cubemap(
+            device,
+            irradiance_size,
+            1,
+            HdrPipeline::FORMAT,
+            usage,
+            Some("environment_irradiance"),
+        );
+        let specular_size = self.settings.specular_size.max(1);
+        let specular_mips = SPECULAR_MIPS.min(specular_size.ilog2() + 1);
+        let prefiltered = Texture::create_cubemap(
+            device,
+            specular_size,
+            specular_mips,
+            HdrPipeline::FORMAT,
+            usage,
+            Some("environment_prefiltered"),
+        );
+
+        let generator = &self.generator;
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("IBL Encoder"),
+        });
+
+        for mip in 0..radiance_mips {
+            let source_view = if mip == 0 {
+                &source.view
+            } else {
+                &IblGenerator::mip_view(&radiance.texture, mip - 1)
+            };
+            for face in 0..6 {
+                generator.pass(
+                    device,
+                    &mut encoder,
+                    &generator.downsample,
+                    source_view,
+                    IblParams {
+                        face,
+                        ..Default::default()
+                    },
+                    &IblGenerator::face_view(&radiance.texture, face, mip),
+                );
+            }
+        }
+
+        for face in 0..6 {
+            generator.pass(
+                device,
+                &mut encoder,
+                &generator.irradiance,
+                &radiance.view,
+                IblParams {
+                    face,
+                    roughness: 1.0,
+                    source_size: radiance_size as f32,
+                    num_samples: 256,
+                },
+                &IblGenerator::face_view(&irradiance.texture, face, 0),
+            );
+        }
+
+        for mip in 0..specular_mips {
+            let roughness = mip as f32 / (specular_mips - 1).max(1) as f32;
+            for face in 0..6 {
+                generator.pass(
+                    device,
+                    &mut encoder,
+                    &generator.prefilter,
+                    &radiance.view,
+                    IblParams {
+                        face,
+                        roughness: roughness.max(0.01),
+                        source_size: radiance_size as f32,
+                        num_samples: 128,
+                    },
+                    &IblGenerator::face_view(&prefiltered.texture, face, mip),
+                );
+            }
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        self.irradiance = irradiance;
+        self.prefiltered = prefiltered;
+        self.update_bind_group(device);
+        self.write_uniform(queue);
+    }
+
+    fn update_bind_group(&mut self, device: &wgpu::Device) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.layout,
+            &self.irradiance,
+            &self.prefiltered,
+            &self.brdf_lut,
+            &self.occlusion,
+            &self.uniform_buffer,
+        );
+    }
+}
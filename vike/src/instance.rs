@@ -0,0 +1,90 @@
+use std::ops::Range;
+
+use crate::{game_object::InstanceRaw, stats::FrameStats};
+
+const MIN_INSTANCES: u64 = 64;
+const INSTANCE_SIZE: u64 = std::mem::size_of::<InstanceRaw>() as u64;
+
+pub struct InstanceBuffer {
+    chunks: Vec<wgpu::Buffer>,
+    chunk_capacity: u64,
+}
+
+impl InstanceBuffer {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let chunk_capacity = (device.limits().max_buffer_size / INSTANCE_SIZE).min(u32::MAX as u64);
+
+        Self {
+            chunks: vec![Self::create_chunk(device, MIN_INSTANCES)],
+            chunk_capacity,
+        }
+    }
+
+    fn create_chunk(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: capacity * INSTANCE_SIZE,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        instances: &[InstanceRaw],
+        stats: &mut FrameStats,
+    ) {
+        let num_chunks = (instances.len() as u64)
+            .div_ceil(self.chunk_capacity)
+            .max(1) as usize;
+        self.chunks.truncate(num_chunks);
+
+        for (i, data) in instances
+            .chunks(self.chunk_capacity as usize)
+            .chain(std::iter::once(&[][..]))
+            .take(num_chunks)
+            .enumerate()
+        {
+            let required = (data.len() as u64).max(MIN_INSTANCES);
+            let capacity = self.chunks.get(i).map(|chunk| chunk.size() / INSTANCE_SIZE);
+            let resize = match capacity {
+                Some(capacity) => capacity < required || capacity > required * 4,
+                None => true,
+            };
+            if resize {
+                let capacity = required.next_power_of_two().min(self.chunk_capacity);
+                let chunk = Self::create_chunk(device, capacity);
+                if i < self.chunks.len() {
+                    self.chunks[i] = chunk;
+                } else {
+                    self.chunks.push(chunk);
+                }
+            }
+
+            if !data.is_empty() {
+                stats.write(queue, &self.chunks[i], 0, bytemuck::cast_slice(data));
+            }
+        }
+    }
+
+    pub fn draws(
+        &self,
+        instances: Range<u32>,
+    ) -> impl Iterator<Item = (&wgpu::Buffer, Range<u32>)> {
+        let capacity = self.chunk_capacity as u32;
+        let chunks = if instances.is_empty() {
+            0..0
+        } else {
+            instances.start / capacity..(instances.end - 1) / capacity + 1
+        };
+
+        chunks.map(move |i| {
+            let offset = i * capacity;
+            let start = instances.start.max(offset) - offset;
+            let end = instances.end.min(offset + capacity) - offset;
+            (&self.chunks[i as usize], start..end)
+        })
+    }
+}
@@ -18,17 +18,31 @@ use winit::{
 };
 
 use crate::camera::CameraController;
+use crate::debug::Debug;
+use crate::stats::FrameStats;
 
+pub mod assets;
+pub mod bloom;
 pub mod camera;
+pub mod cluster;
 pub mod debug;
+pub mod depth;
+pub mod exposure;
 pub mod game_object;
 pub mod hdr;
+pub mod ibl;
+pub mod instance;
+pub mod postprocess;
 pub mod renderer;
 pub mod resources;
+pub mod shadow;
+pub mod skybox;
+pub mod ssao;
+pub mod stats;
+pub mod text;
 pub mod texture;
 
 const MAX_LIGHTS: usize = 128;
-const MAX_INSTANCES: usize = 131072;
 
 pub enum RenderMode {
     Window,
@@ -47,6 +61,7 @@ pub trait WindowedVike {
         &mut self,
         game_objects: &mut GameObjectStore,
         camera_controller: &mut CameraController,
+        debug: &mut Debug,
         dt: Duration,
     ) -> impl std::future::Future<Output = ()>;
 
@@ -128,7 +143,12 @@ pub async fn run_windowed(
                     let dt = now - last_instant;
                     last_instant = now;
 
-                    block_on(controller.update(&mut game_objects, &mut camera_controller, dt));
+                    block_on(controller.update(
+                        &mut game_objects,
+                        &mut camera_controller,
+                        &mut renderer.debug,
+                        dt,
+                    ));
 
                     camera_controller.update_camera(&mut renderer.camera, dt);
 
@@ -170,12 +190,14 @@ pub trait HeadlessVike {
         &mut self,
         game_objects: &mut GameObjectStore,
         camera_controller: &mut CameraController,
+        debug: &mut Debug,
         dt: Duration,
     ) -> impl std::future::Future<Output = ()>;
 
     fn frame(
         &mut self,
         image_buffer: ImageBuffer<Rgba<u8>, Vec<u8>>,
+        stats: &FrameStats,
     ) -> impl std::future::Future<Output = ()>;
 }
 
@@ -204,7 +226,12 @@ pub async fn run_headless(width: u32, height: u32, controller: &mut impl Headles
         last_instant = now;
 
         controller
-            .update(&mut game_objects, &mut camera_controller, dt)
+            .update(
+                &mut game_objects,
+                &mut camera_controller,
+                &mut renderer.debug,
+                dt,
+            )
             .await;
 
         camera_controller.update_camera(&mut renderer.camera, dt);
@@ -212,7 +239,7 @@ pub async fn run_headless(width: u32, height: u32, controller: &mut impl Headles
         renderer.render(&game_objects).unwrap();
 
         controller
-            .frame(renderer.image_buffer().await.unwrap())
+            .frame(renderer.image_buffer().await.unwrap(), renderer.stats())
             .await;
     }
 }
@@ -0,0 +1,437 @@
+use anyhow::{Result, anyhow};
+use futures_lite::future::block_on;
+use wgpu::util::DeviceExt;
+
+use crate::{stats::FrameStats, texture::Texture};
+
+const DEPTH_BINDING: &str = "@group(0) @binding(2)\nvar depth_texture: texture_2d<f32>;\n";
+
+#[derive(Clone, Debug)]
+pub struct PostProcessPass {
+    pub name: String,
+    pub shader: String,
+    pub uniforms: Option<Vec<u8>>,
+    pub depth: bool,
+}
+
+impl PostProcessPass {
+    pub fn new(name: &str, shader: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            shader: shader.to_string(),
+            uniforms: None,
+            depth: false,
+        }
+    }
+}
+
+struct PostProcessEffect {
+    name: String,
+    pipeline: wgpu::RenderPipeline,
+    uniforms: Option<(wgpu::Buffer, wgpu::BindGroup)>,
+    depth: bool,
+}
+
+pub struct PostProcessChain {
+    effects: Vec<PostProcessEffect>,
+    input_layout: wgpu::BindGroupLayout,
+    depth_layout: wgpu::BindGroupLayout,
+    uniform_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    format: wgpu::TextureFormat,
+    targets: [Texture; 2],
+    input_bind_groups: [wgpu::BindGroup; 2],
+    depth_bind_groups: [wgpu::BindGroup; 2],
+}
+
+impl PostProcessChain {
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        depth_view: &wgpu::TextureView,
+    ) -> Self {
+        let texture_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let sampler_entry = wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let depth_entry = wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        let input_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry, sampler_entry],
+            label: Some("post_process_input_layout"),
+        });
+        let depth_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry, sampler_entry, depth_entry],
+            label: Some("post_process_depth_layout"),
+        });
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("post_process_uniform_layout"),
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let targets = Self::create_targets(device, width, height, format);
+        let (input_bind_groups, depth_bind_groups) = Self::create_bind_groups(
+            device,
+            &input_layout,
+            &depth_layout,
+            &sampler,
+            &targets,
+            depth_view,
+        );
+
+        Self {
+            effects: Vec::new(),
+            input_layout,
+            depth_layout,
+            uniform_layout,
+            sampler,
+            format,
+            targets,
+            input_bind_groups,
+            depth_bind_groups,
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> [Texture; 2] {
+        [0, 1].map(|_| {
+            Texture::create_2d_texture(
+                device,
+                width,
+                height,
+                format,
+                wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                wgpu::FilterMode::Linear,
+                Some("post_process_texture"),
+            )
+        })
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        input_layout: &wgpu::BindGroupLayout,
+        depth_layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        targets: &[Texture; 2],
+        depth_view: &wgpu::TextureView,
+    ) -> ([wgpu::BindGroup; 2], [wgpu::BindGroup; 2]) {
+        let input = |target: &Texture| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: input_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&target.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                ],
+                label: Some("post_process_input_bind_group"),
+            })
+        };
+        let depth = |target: &Texture| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: depth_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&target.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(depth_view),
+                    },
+                ],
+                label: Some("post_process_depth_bind_group"),
+            })
+        };
+
+        (
+            [input(&targets[0]), input(&targets[1])],
+            [depth(&targets[0]), depth(&targets[1])],
+        )
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        depth_view: &wgpu::TextureView,
+    ) {
+        self.targets = Self::create_targets(device, width, height, self.format);
+        self.set_depth_view(device, depth_view);
+    }
+
+    pub fn set_depth_view(&mut self, device: &wgpu::Device, depth_view: &wgpu::TextureView) {
+        (self.input_bind_groups, self.depth_bind_groups) = Self::create_bind_groups(
+            device,
+            &self.input_layout,
+            &self.depth_layout,
+            &self.sampler,
+            &self.targets,
+            depth_view,
+        );
+    }
+
+    fn create_uniforms(
+        &self,
+        device: &wgpu::Device,
+        name: &str,
+        contents: &[u8],
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let mut contents = contents.to_vec();
+        contents.resize(contents.len().next_multiple_of(16).max(16), 0);
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Uniform Buffer", name)),
+            contents: &contents,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.uniform_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("post_process_uniform_bind_group"),
+        });
+
+        (buffer, bind_group)
+    }
+
+    fn create_effect(
+        &self,
+        device: &wgpu::Device,
+        pass: &PostProcessPass,
+    ) -> Result<PostProcessEffect> {
+        let mut source = include_str!("../shaders/postprocess.wgsl").to_string();
+        if pass.depth {
+            source.push_str(DEPTH_BINDING);
+        }
+        source.push('\n');
+        source.push_str(&pass.shader);
+
+        let uniforms = pass
+            .uniforms
+            .as_ref()
+            .map(|contents| self.create_uniforms(device, &pass.name, contents));
+
+        let input_layout = if pass.depth {
+            &self.depth_layout
+        } else {
+            &self.input_layout
+        };
+        let mut bind_group_layouts = vec![input_layout];
+        if uniforms.is_some() {
+            bind_group_layouts.push(&self.uniform_layout);
+        }
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(&pass.name),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&pass.name),
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&pass.name),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: self.format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        if let Some(error) = block_on(device.pop_error_scope()) {
+            return Err(anyhow!("post process {:?}: {}", pass.name, error));
+        }
+
+        Ok(PostProcessEffect {
+            name: pass.name.clone(),
+            pipeline,
+            uniforms,
+            depth: pass.depth,
+        })
+    }
+
+    pub fn insert(
+        &mut self,
+        device: &wgpu::Device,
+        index: usize,
+        pass: &PostProcessPass,
+    ) -> Result<()> {
+        let effect = self.create_effect(device, pass)?;
+        self.effects.retain(|effect| effect.name != pass.name);
+        self.effects.insert(index.min(self.effects.len()), effect);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.effects.len();
+        self.effects.retain(|effect| effect.name != name);
+        self.effects.len() != len
+    }
+
+    pub fn reorder(&mut self, name: &str, index: usize) -> bool {
+        let Some(position) = self.effects.iter().position(|effect| effect.name == name) else {
+            return false;
+        };
+        let effect = self.effects.remove(position);
+        self.effects.insert(index.min(self.effects.len()), effect);
+        true
+    }
+
+    pub fn set_uniforms(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        name: &str,
+        contents: &[u8],
+    ) -> bool {
+        let Some(position) = self.effects.iter().position(|effect| effect.name == name) else {
+            return false;
+        };
+
+        match &self.effects[position].uniforms {
+            Some((buffer, _)) if contents.len() as u64 <= buffer.size() => {
+                queue.write_buffer(buffer, 0, contents);
+            }
+            Some(_) => {
+                let uniforms = self.create_uniforms(device, name, contents);
+                self.effects[position].uniforms = Some(uniforms);
+            }
+            None => {
+                log::warn!("post process {:?} was created without uniforms", name);
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.effects.iter().map(|effect| effect.name.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+
+    pub fn needs_depth(&self) -> bool {
+        self.effects.iter().any(|effect| effect.depth)
+    }
+
+    pub fn input_view(&self) -> &wgpu::TextureView {
+        &self.targets[0].view
+    }
+
+    pub fn process(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        stats: &mut FrameStats,
+    ) {
+        for (i, effect) in self.effects.iter().enumerate() {
+            let target = if i + 1 == self.effects.len() {
+                output
+            } else {
+                &self.targets[(i + 1) % 2].view
+            };
+
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(&effect.name),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            pass.set_pipeline(&effect.pipeline);
+            if effect.depth {
+                pass.set_bind_group(0, &self.depth_bind_groups[i % 2], &[]);
+            } else {
+                pass.set_bind_group(0, &self.input_bind_groups[i % 2], &[]);
+            }
+            if let Some((_, bind_group)) = &effect.uniforms {
+                pass.set_bind_group(1, bind_group, &[]);
+            }
+            pass.draw(0..3, 0..1);
+            stats.pipeline();
+            stats.draw(1);
+        }
+    }
+}
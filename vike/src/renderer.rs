@@ -1,20 +1,31 @@
 use anyhow::Result;
 use cfg_if::cfg_if;
-use glam::Vec3;
+use glam::{Vec2, Vec3};
 use image::{ImageBuffer, Rgba};
 use std::sync::Arc;
+use web_time::{Duration, Instant};
 use wgpu::util::DeviceExt;
 use winit::{event::WindowEvent, window::Window};
 
 use crate::{
-    MAX_INSTANCES,
-    camera::{Camera, CameraUniform, Projection},
-    debug::Debug,
+    bloom::{BloomPipeline, BloomSettings},
+    camera::{Camera, CameraUniform, Frustum, Projection},
+    cluster::{ClusterSettings, ClusteredLights},
+    debug::{Debug, DebugView},
+    depth::DepthPrepass,
+    exposure::AutoExposureSettings,
     game_object::{
-        DrawLight, DrawModel, GameObjectStore, InstanceRaw, LightUniform, ModelVertex, Transform3D,
-        Vertex,
+        AlphaMode, CullingStats, DrawLight, DrawModel, GameObjectStore, InstanceRaw, Material,
+        MaterialFactors, ModelVertex, Vertex,
     },
-    hdr::HdrPipeline,
+    hdr::{HdrPipeline, ToneMapping},
+    ibl::{Environment, IblSettings},
+    instance::InstanceBuffer,
+    postprocess::{PostProcessChain, PostProcessPass},
+    shadow::{ShadowPipeline, ShadowSettings},
+    skybox::{Sky, SkyboxPipeline},
+    ssao::{SsaoPipeline, SsaoSettings},
+    stats::{FrameStats, GpuTimer},
     texture::Texture,
 };
 
@@ -43,26 +54,58 @@ pub enum RenderOutput {
     },
 }
 
-#[allow(dead_code)]
+// Material pipeline variants are indexed by whether the material is double-sided
+struct ScenePipelines {
+    opaque: [wgpu::RenderPipeline; 2],
+    blended: [wgpu::RenderPipeline; 2],
+    light: wgpu::RenderPipeline,
+}
+
+impl ScenePipelines {
+    fn material(&self, factors: &MaterialFactors) -> &wgpu::RenderPipeline {
+        let variants = match factors.alpha_mode {
+            AlphaMode::Blend => &self.blended,
+            _ => &self.opaque,
+        };
+        &variants[factors.double_sided as usize]
+    }
+}
+
 pub struct Renderer {
     device: wgpu::Device,
     queue: wgpu::Queue,
     output: RenderOutput,
-    render_pipeline: wgpu::RenderPipeline,
-    light_render_pipeline: wgpu::RenderPipeline,
+    pipelines: ScenePipelines,
     texture_bind_group_layout: wgpu::BindGroupLayout,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
     depth_texture: Texture,
     hdr: HdrPipeline,
+    bloom: BloomPipeline,
+    post_process: PostProcessChain,
+    depth_prepass: DepthPrepass,
+    ssao: Option<SsaoPipeline>,
+    shadow: ShadowPipeline,
+    skybox: SkyboxPipeline,
+    environment: Environment,
+    clear_color: wgpu::Color,
     pub camera: Camera,
     projection: Projection,
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
-    instance_buffer: wgpu::Buffer,
-    light_buffer: wgpu::Buffer,
+    instance_buffer: InstanceBuffer,
+    clusters: ClusteredLights,
     light_bind_group_layout: wgpu::BindGroupLayout,
     light_bind_group: wgpu::BindGroup,
+    culling_stats: CullingStats,
+    stats: FrameStats,
+    gpu_timer: Option<GpuTimer>,
+    last_frame: Option<Instant>,
+    stats_overlay: bool,
+    sample_count: u32,
+    supported_sample_counts: Vec<u32>,
     pub debug: Debug,
+    debug_view: DebugView,
 }
 
 impl Renderer {
@@ -109,10 +152,34 @@ impl Renderer {
             .await
             .unwrap();
 
+        let required_features = adapter.features()
+            & (wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                | wgpu::Features::POLYGON_MODE_LINE
+                | wgpu::Features::TIMESTAMP_QUERY);
+        let supported_sample_counts = if !required_features
+            .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+        {
+            vec![1, 4]
+        } else {
+            let hdr_flags = adapter
+                .get_texture_format_features(HdrPipeline::FORMAT)
+                .flags;
+            let depth_flags = adapter
+                .get_texture_format_features(Texture::DEPTH_FORMAT)
+                .flags;
+            [1, 2, 4, 8]
+                .into_iter()
+                .filter(|count| {
+                    hdr_flags.sample_count_supported(*count)
+                        && depth_flags.sample_count_supported(*count)
+                })
+                .collect()
+        };
+
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    required_features: wgpu::Features::empty(),
+                    required_features,
                     required_limits: if cfg!(target_arch = "wasm32") {
                         wgpu::Limits::downlevel_webgl2_defaults()
                     } else {
@@ -201,49 +268,42 @@ impl Renderer {
             )
         };
 
+        let mut texture_layout_entries = Vec::new();
+        for i in 0..Material::NUM_TEXTURES {
+            texture_layout_entries.push(wgpu::BindGroupLayoutEntry {
+                binding: i * 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            });
+            texture_layout_entries.push(wgpu::BindGroupLayoutEntry {
+                binding: i * 2 + 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            });
+        }
+        texture_layout_entries.push(wgpu::BindGroupLayoutEntry {
+            binding: Material::NUM_TEXTURES * 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        });
+
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
+                entries: &texture_layout_entries,
                 label: Some("texture_bind_group_layout"),
             });
 
-        let depth_texture = Texture::create_depth_texture(&device, width, height, "depth_texture");
-
-        let hdr = HdrPipeline::new(&device, width, height, format);
-
         let camera = Camera::new(
             [0.0, 5.0, 10.0],
             -90.0_f32.to_radians(),
@@ -284,83 +344,217 @@ impl Renderer {
             label: Some("camera_bind_group"),
         });
 
-        let instances = (0..MAX_INSTANCES).map(move |_| Transform3D {
-            position: Vec3::new(0.0, 0.0, 0.0),
-            rotation: Vec3::new(0.0, 0.0, 0.0),
-            scale: Vec3::new(1.0, 1.0, 1.0),
-        });
-
-        let instance_data: Vec<InstanceRaw> = instances
-            .map(|i| i.to_raw_instance())
-            .collect::<Vec<InstanceRaw>>();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Transform3D Buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
+        let instance_buffer = InstanceBuffer::new(&device);
 
-        let light_uniform = LightUniform::default();
-        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Light VB"),
-            contents: bytemuck::cast_slice(&[light_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+        let clusters = ClusteredLights::new(&device, ClusterSettings::default());
 
-        let light_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+        let light_bind_group_layout = {
+            let mut entries = vec![
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
                         min_binding_size: None,
                     },
                     count: None,
-                }],
+                },
+            ];
+            entries.extend(clusters.layout_entries());
+
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &entries,
                 label: Some("light_bind_group_layout"),
-            });
+            })
+        };
 
-        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
-            label: Some("light_bind_group"),
-        });
+        let shadow = ShadowPipeline::new(
+            &device,
+            &texture_bind_group_layout,
+            ShadowSettings::default(),
+        );
+
+        let light_bind_group =
+            Self::create_light_bind_group(&device, &light_bind_group_layout, &clusters, &shadow);
+
+        let sample_count = Self::supported_sample_count(&supported_sample_counts, 4);
+        let depth_texture =
+            Texture::create_depth_texture(&device, width, height, sample_count, "depth_texture");
+        let hdr = HdrPipeline::new(&device, width, height, sample_count, format);
+        let bloom =
+            BloomPipeline::new(&device, hdr.view(), width, height, BloomSettings::default());
+        let depth_prepass = DepthPrepass::new(&device, &texture_bind_group_layout, width, height);
+        let post_process = PostProcessChain::new(
+            &device,
+            width,
+            height,
+            format,
+            Self::scene_depth_view(sample_count, &depth_texture, &depth_prepass),
+        );
+
+        let environment = Environment::new(&device, &queue, IblSettings::default());
+
+        let pipelines = Self::create_scene_pipelines(
+            &device,
+            [
+                &texture_bind_group_layout,
+                &camera_bind_group_layout,
+                &light_bind_group_layout,
+                environment.layout(),
+            ],
+            clusters.shader_header(),
+            DebugView::None,
+            hdr.format(),
+            sample_count,
+        );
+
+        let skybox = SkyboxPipeline::new(&device, &queue, &camera_bind_group_layout, sample_count);
 
-        let render_pipeline = {
+        let debug = Debug::new(&device, &queue, &camera_bind_group_layout, format);
+        let gpu_timer = GpuTimer::new(&device, &queue);
+
+        Self {
+            device,
+            queue,
+            output,
+            pipelines,
+            texture_bind_group_layout,
+            camera_bind_group_layout,
+            depth_texture,
+            hdr,
+            bloom,
+            post_process,
+            depth_prepass,
+            ssao: None,
+            shadow,
+            skybox,
+            environment,
+            clear_color: wgpu::Color {
+                r: 0.1,
+                g: 0.2,
+                b: 0.3,
+                a: 1.0,
+            },
+            camera,
+            projection,
+            camera_uniform,
+            camera_buffer,
+            camera_bind_group,
+            instance_buffer,
+            clusters,
+            light_bind_group_layout,
+            light_bind_group,
+            culling_stats: CullingStats::default(),
+            stats: FrameStats::default(),
+            gpu_timer,
+            last_frame: None,
+            stats_overlay: false,
+            sample_count,
+            supported_sample_counts,
+            debug,
+            debug_view: DebugView::None,
+        }
+    }
+
+    fn create_scene_pipelines(
+        device: &wgpu::Device,
+        [
+            texture_layout,
+            camera_layout,
+            light_layout,
+            environment_layout,
+        ]: [&wgpu::BindGroupLayout; 4],
+        light_header: &str,
+        debug_view: DebugView,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> ScenePipelines {
+        let (opaque, blended) = {
             let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
                 bind_group_layouts: &[
-                    &texture_bind_group_layout,
-                    &camera_bind_group_layout,
-                    &light_bind_group_layout,
+                    texture_layout,
+                    camera_layout,
+                    light_layout,
+                    environment_layout,
                 ],
                 push_constant_ranges: &[],
             });
 
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Normal Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/shader.wgsl").into()),
+            let source = format!(
+                "{}\n{}\n{}",
+                light_header,
+                debug_view.shader_header(),
+                include_str!("../shaders/shader.wgsl")
+            );
+            let polygon_mode = match debug_view {
+                DebugView::Wireframe => wgpu::PolygonMode::Line,
+                _ => wgpu::PolygonMode::Fill,
             };
-
-            Self::create_render_pipeline(
-                &device,
-                &layout,
-                hdr.format(),
-                Some(Texture::DEPTH_FORMAT),
-                &[ModelVertex::desc(), InstanceRaw::desc()],
-                wgpu::PrimitiveTopology::TriangleList,
-                shader,
-            )
+            let create = |blend, cull_mode| {
+                let shader = wgpu::ShaderModuleDescriptor {
+                    label: Some("Normal Shader"),
+                    source: wgpu::ShaderSource::Wgsl(source.as_str().into()),
+                };
+                Self::create_render_pipeline(
+                    device,
+                    &layout,
+                    color_format,
+                    blend,
+                    Some(Texture::DEPTH_FORMAT),
+                    &[ModelVertex::desc(), InstanceRaw::desc()],
+                    wgpu::PrimitiveState {
+                        cull_mode,
+                        polygon_mode,
+                        ..Default::default()
+                    },
+                    sample_count,
+                    shader,
+                )
+            };
+            let variants = |blend| [create(blend, Some(wgpu::Face::Back)), create(blend, None)];
+
+            match debug_view {
+                DebugView::Overdraw => {
+                    let additive = wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    };
+                    let blend = Some(wgpu::BlendState {
+                        color: additive,
+                        alpha: additive,
+                    });
+                    (variants(blend), variants(blend))
+                }
+                _ => (
+                    variants(None),
+                    variants(Some(wgpu::BlendState::ALPHA_BLENDING)),
+                ),
+            }
         };
 
-        let light_render_pipeline = {
+        let light = {
             let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Light Pipeline Layout"),
-                bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
+                bind_group_layouts: &[camera_layout, light_layout],
                 push_constant_ranges: &[],
             });
 
@@ -370,47 +564,61 @@ impl Renderer {
             };
 
             Self::create_render_pipeline(
-                &device,
+                device,
                 &layout,
-                hdr.format(),
+                color_format,
+                None,
                 Some(Texture::DEPTH_FORMAT),
                 &[ModelVertex::desc(), InstanceRaw::desc()],
-                wgpu::PrimitiveTopology::TriangleList,
+                wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                sample_count,
                 shader,
             )
         };
 
-        let debug = Debug::new(&device, &camera_bind_group_layout, format);
+        ScenePipelines {
+            opaque,
+            blended,
+            light,
+        }
+    }
 
-        Self {
-            device,
-            queue,
-            output,
-            render_pipeline,
-            light_render_pipeline,
-            texture_bind_group_layout,
-            depth_texture,
-            hdr,
-            camera,
-            projection,
-            camera_uniform,
-            camera_buffer,
-            camera_bind_group,
-            instance_buffer,
-            light_buffer,
-            light_bind_group_layout,
-            light_bind_group,
-            debug,
+    // Multisampled depth can't be sampled, so passes that read depth fall back
+    // to the single-sample prepass
+    fn scene_depth_view<'a>(
+        sample_count: u32,
+        depth_texture: &'a Texture,
+        depth_prepass: &'a DepthPrepass,
+    ) -> &'a wgpu::TextureView {
+        if sample_count > 1 {
+            depth_prepass.view()
+        } else {
+            &depth_texture.view
         }
     }
 
+    fn supported_sample_count(supported: &[u32], requested: u32) -> u32 {
+        supported
+            .iter()
+            .copied()
+            .filter(|count| *count <= requested.max(1))
+            .max()
+            .unwrap_or(1)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn create_render_pipeline(
         device: &wgpu::Device,
         layout: &wgpu::PipelineLayout,
         color_format: wgpu::TextureFormat,
+        blend: Option<wgpu::BlendState>,
         depth_format: Option<wgpu::TextureFormat>,
         vertex_layouts: &[wgpu::VertexBufferLayout],
-        topology: wgpu::PrimitiveTopology,
+        primitive: wgpu::PrimitiveState,
+        sample_count: u32,
         shader: wgpu::ShaderModuleDescriptor,
     ) -> wgpu::RenderPipeline {
         let shader = device.create_shader_module(shader);
@@ -428,28 +636,21 @@ impl Renderer {
                 entry_point: "fs_main",
                 targets: &[Some(wgpu::ColorTargetState {
                     format: color_format,
-                    blend: None,
+                    blend,
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
             }),
-            primitive: wgpu::PrimitiveState {
-                topology,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
+            primitive,
             depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
                 format,
-                depth_write_enabled: true,
+                // blended geometry is sorted instead, and shouldn't hide what's behind it
+                depth_write_enabled: blend.is_none(),
                 depth_compare: wgpu::CompareFunction::LessEqual,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -457,11 +658,46 @@ impl Renderer {
         })
     }
 
+    fn create_light_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        clusters: &ClusteredLights,
+        shadow: &ShadowPipeline,
+    ) -> wgpu::BindGroup {
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(shadow.view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(shadow.sampler()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: shadow.uniform_buffer().as_entire_binding(),
+            },
+        ];
+        entries.extend(clusters.bind_group_entries());
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &entries,
+            label: Some("light_bind_group"),
+        })
+    }
+
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.projection.resize(new_size.width, new_size.height);
             self.hdr
                 .resize(&self.device, new_size.width, new_size.height);
+            self.bloom.resize(
+                &self.device,
+                self.hdr.view(),
+                new_size.width,
+                new_size.height,
+            );
 
             match &mut self.output {
                 RenderOutput::Surface {
@@ -481,8 +717,27 @@ impl Renderer {
                 &self.device,
                 new_size.width,
                 new_size.height,
+                self.sample_count,
                 "depth_texture",
             );
+            self.depth_prepass
+                .resize(&self.device, new_size.width, new_size.height);
+            self.post_process.resize(
+                &self.device,
+                new_size.width,
+                new_size.height,
+                Self::scene_depth_view(self.sample_count, &self.depth_texture, &self.depth_prepass),
+            );
+            if let Some(ssao) = &mut self.ssao {
+                ssao.resize(
+                    &self.device,
+                    self.depth_prepass.view(),
+                    new_size.width,
+                    new_size.height,
+                );
+                self.environment
+                    .set_occlusion(&self.device, Some(ssao.texture()));
+            }
         }
     }
 
@@ -491,28 +746,62 @@ impl Renderer {
     }
 
     pub fn render(&mut self, game_objects: &GameObjectStore) -> Result<(), wgpu::SurfaceError> {
-        let pre_frame_data = game_objects.pre_frame();
+        let now = Instant::now();
+        let frame_time = self.last_frame.map_or(Duration::ZERO, |last| now - last);
+        self.last_frame = Some(now);
+
+        let view_proj = self.projection.calc_matrix() * self.camera.calc_matrix();
+        let debugging = self.debug_view != DebugView::None;
+        let pre_frame_data =
+            game_objects.pre_frame(&Frustum::from_view_proj(view_proj), self.camera.position);
+        let encode_start = Instant::now();
+        self.culling_stats = pre_frame_data.culling;
+        let mut stats = FrameStats {
+            frame_time,
+            pre_frame_time: encode_start - now,
+            instances: pre_frame_data.culling.visible,
+            lights: pre_frame_data.light_data.len() as u32,
+            ..Default::default()
+        };
 
-        self.queue.write_buffer(
-            &self.light_buffer,
-            0,
-            bytemuck::cast_slice(&[pre_frame_data.light_uniform]),
+        let reallocated = self.clusters.update(
+            &self.device,
+            &self.queue,
+            &pre_frame_data.light_data,
+            self.camera.calc_matrix(),
+            self.projection.calc_matrix(),
+            (self.projection.znear(), self.projection.zfar()),
+            self.size(),
+            &mut stats,
         );
+        if reallocated {
+            self.light_bind_group = Self::create_light_bind_group(
+                &self.device,
+                &self.light_bind_group_layout,
+                &self.clusters,
+                &self.shadow,
+            );
+        }
 
         self.camera_uniform
             .update_view_proj(&self.camera, &self.projection);
-        self.queue.write_buffer(
+        stats.write(
+            &self.queue,
             &self.camera_buffer,
             0,
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
 
-        self.queue.write_buffer(
-            &self.instance_buffer,
-            0,
-            bytemuck::cast_slice(&pre_frame_data.instances),
+        self.instance_buffer.update(
+            &self.device,
+            &self.queue,
+            &pre_frame_data.instances,
+            &mut stats,
         );
 
+        self.shadow
+            .update(&self.queue, &pre_frame_data.shadow_lights, &mut stats);
+
         let (view, surface_texture) = match &mut self.output {
             RenderOutput::Surface { surface, .. } => {
                 let surface_texture = surface.get_current_texture()?;
@@ -531,20 +820,50 @@ impl Renderer {
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.begin(&self.device, &mut encoder);
+        }
+
+        self.shadow.process(
+            &mut encoder,
+            &self.instance_buffer,
+            &pre_frame_data.shadow_objects,
+            &mut stats,
+        );
+        self.mark_pass(&mut encoder, "shadow");
+
+        if self.ssao.is_some()
+            || (self.sample_count > 1
+                && (self.post_process.needs_depth() || self.debug.needs_depth()))
+        {
+            self.depth_prepass.process(
+                &mut encoder,
+                &self.queue,
+                view_proj,
+                &self.instance_buffer,
+                pre_frame_data.objects.iter().chain(&pre_frame_data.lights),
+                &mut stats,
+            );
+            self.mark_pass(&mut encoder, "depth_prepass");
+        }
+        if let Some(ssao) = &self.ssao {
+            ssao.process(
+                &mut encoder,
+                &self.queue,
+                self.projection.calc_matrix(),
+                &mut stats,
+            );
+            self.mark_pass(&mut encoder, "ssao");
+        }
 
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: self.hdr.view(),
-                    resolve_target: None,
+                    view: self.hdr.render_view(),
+                    resolve_target: self.hdr.resolve_target(),
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
+                        load: wgpu::LoadOp::Clear(self.clear_color),
                         store: wgpu::StoreOp::Store,
                     },
                 })],
@@ -560,30 +879,118 @@ impl Renderer {
                 timestamp_writes: None,
             });
 
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            for double_sided in [false, true] {
+                render_pass.set_pipeline(&self.pipelines.opaque[double_sided as usize]);
+                stats.pipeline();
+                render_pass.set_bind_group(3, self.environment.bind_group(), &[]);
+                for (model, meshes, range) in &pre_frame_data.objects {
+                    for (buffer, range) in self.instance_buffer.draws(range.clone()) {
+                        render_pass.set_vertex_buffer(1, buffer.slice(..));
+                        for mesh in &model.meshes[meshes.clone()] {
+                            let factors = model.factors(mesh);
+                            // blended meshes are drawn after the skybox, sorted back to front
+                            if factors.alpha_mode == AlphaMode::Blend
+                                || factors.double_sided != double_sided
+                            {
+                                continue;
+                            }
+                            render_pass.draw_mesh_instanced(
+                                mesh,
+                                &model.materials[mesh.material],
+                                range.clone(),
+                                &self.camera_bind_group,
+                                &self.light_bind_group,
+                            );
+                            stats.draw(mesh.triangles() as u64 * range.len() as u64);
+                        }
+                    }
+                }
+            }
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            for (model, range) in &pre_frame_data.objects {
-                render_pass.draw_model_instanced(
-                    model,
-                    range.clone(),
-                    &self.camera_bind_group,
-                    &self.light_bind_group,
-                );
+            render_pass.set_pipeline(&self.pipelines.light);
+            stats.pipeline();
+            for (model, meshes, range) in &pre_frame_data.lights {
+                for (buffer, range) in self.instance_buffer.draws(range.clone()) {
+                    render_pass.set_vertex_buffer(1, buffer.slice(..));
+                    for mesh in &model.meshes[meshes.clone()] {
+                        stats.draw(mesh.triangles() as u64 * range.len() as u64);
+                        render_pass.draw_light_mesh_instanced(
+                            mesh,
+                            range.clone(),
+                            &self.camera_bind_group,
+                            &self.light_bind_group,
+                        );
+                    }
+                }
             }
 
-            render_pass.set_pipeline(&self.light_render_pipeline);
-            for (model, range) in &pre_frame_data.lights {
-                render_pass.draw_light_model_instanced(
-                    model,
-                    range.clone(),
-                    &self.camera_bind_group,
-                    &self.light_bind_group,
-                );
+            if !debugging {
+                self.skybox
+                    .draw(&mut render_pass, &self.camera_bind_group, &mut stats);
+            }
+
+            for (model, mesh, range) in &pre_frame_data.transparent {
+                let mesh = &model.meshes[*mesh];
+                render_pass.set_pipeline(self.pipelines.material(&model.factors(mesh)));
+                stats.pipeline();
+                render_pass.set_bind_group(3, self.environment.bind_group(), &[]);
+                for (buffer, range) in self.instance_buffer.draws(range.clone()) {
+                    render_pass.set_vertex_buffer(1, buffer.slice(..));
+                    stats.draw(mesh.triangles() as u64 * range.len() as u64);
+                    render_pass.draw_mesh_instanced(
+                        mesh,
+                        &model.materials[mesh.material],
+                        range,
+                        &self.camera_bind_group,
+                        &self.light_bind_group,
+                    );
+                }
             }
         }
 
-        self.hdr.process(&mut encoder, &view);
+        self.mark_pass(&mut encoder, "main");
+
+        if !debugging {
+            self.bloom
+                .process(&mut encoder, &self.queue, self.hdr.view(), &mut stats);
+            self.mark_pass(&mut encoder, "bloom");
+        }
+        if self.post_process.is_empty() || debugging {
+            self.hdr
+                .process(&mut encoder, &self.queue, &view, &mut stats);
+        } else {
+            self.hdr.process(
+                &mut encoder,
+                &self.queue,
+                self.post_process.input_view(),
+                &mut stats,
+            );
+            self.post_process.process(&mut encoder, &view, &mut stats);
+        }
+        self.mark_pass(&mut encoder, "post_process");
+
+        if self.stats_overlay {
+            // the previous frame, since this one is still being counted
+            let fps = 1.0 / self.stats.frame_time.as_secs_f32().max(f32::EPSILON);
+            let mut text = format!(
+                "{:.0} fps\n{:.2} ms\n{} draw calls\n{} instances\n{} lights\n{} triangles",
+                fps,
+                self.stats.frame_time.as_secs_f64() * 1000.0,
+                self.stats.draw_calls,
+                self.stats.instances,
+                self.stats.lights,
+                self.stats.triangles,
+            );
+            if !self.stats.gpu_timings.is_empty() {
+                text += &format!(
+                    "\n{:.2} ms gpu",
+                    self.stats.gpu_time().as_secs_f64() * 1000.0
+                );
+            }
+            self.debug.text(Vec2::splat(8.0), 2.0, &text, Vec3::ONE);
+        }
+        self.debug
+            .prepare(&self.device, &self.queue, self.size(), &mut stats);
 
         {
             let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -596,52 +1003,74 @@ impl Renderer {
                         store: wgpu::StoreOp::Store,
                     },
                 })],
-                depth_stencil_attachment: None,
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: Self::scene_depth_view(
+                        self.sample_count,
+                        &self.depth_texture,
+                        &self.depth_prepass,
+                    ),
+                    depth_ops: None,
+                    stencil_ops: None,
+                }),
                 occlusion_query_set: None,
                 timestamp_writes: None,
             });
-            self.debug.draw_axis(&mut pass, &self.camera_bind_group);
+            self.debug
+                .draw(&mut pass, &self.camera_bind_group, &mut stats);
         }
-
-        match &mut self.output {
-            RenderOutput::Surface { .. } => {
-                self.queue.submit(std::iter::once(encoder.finish()));
-
-                if let Some(texture) = surface_texture {
-                    texture.present();
-                }
-            }
-            RenderOutput::Buffer {
-                padded_bytes_per_row,
-                texture,
-                buffer,
-                ..
-            } => {
-                encoder.copy_texture_to_buffer(
-                    wgpu::ImageCopyTexture {
-                        aspect: wgpu::TextureAspect::All,
-                        texture,
-                        mip_level: 0,
-                        origin: wgpu::Origin3d::ZERO,
-                    },
-                    wgpu::ImageCopyBuffer {
-                        buffer,
-                        layout: wgpu::ImageDataLayout {
-                            offset: 0,
-                            bytes_per_row: Some(*padded_bytes_per_row),
-                            rows_per_image: None,
-                        },
+        self.debug.clear();
+        self.mark_pass(&mut encoder, "debug");
+
+        if let RenderOutput::Buffer {
+            padded_bytes_per_row,
+            texture,
+            buffer,
+            ..
+        } = &self.output
+        {
+            encoder.copy_texture_to_buffer(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                wgpu::ImageCopyBuffer {
+                    buffer,
+                    layout: wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(*padded_bytes_per_row),
+                        rows_per_image: None,
                     },
-                    texture.size(),
-                );
+                },
+                texture.size(),
+            );
+        }
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.resolve(&mut encoder);
+        }
 
-                self.queue.submit(std::iter::once(encoder.finish()));
-            }
+        let command_buffer = encoder.finish();
+        stats.encode_time = encode_start.elapsed();
+        self.queue.submit(std::iter::once(command_buffer));
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.map();
+            stats.gpu_timings = timer.timings().to_vec();
+        }
+        if let Some(texture) = surface_texture {
+            texture.present();
         }
+        self.stats = stats;
 
         Ok(())
     }
 
+    fn mark_pass(&mut self, encoder: &mut wgpu::CommandEncoder, label: &'static str) {
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.mark(encoder, label);
+        }
+    }
+
     pub fn window(&self) -> Option<&Window> {
         match &self.output {
             RenderOutput::Surface { window, .. } => Some(window),
@@ -707,4 +1136,233 @@ impl Renderer {
     pub fn texture_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
         &self.texture_bind_group_layout
     }
+
+    pub fn shadow_settings(&self) -> &ShadowSettings {
+        self.shadow.settings()
+    }
+
+    pub fn set_shadow_settings(&mut self, settings: ShadowSettings) {
+        self.shadow.set_settings(&self.device, settings);
+        self.light_bind_group = Self::create_light_bind_group(
+            &self.device,
+            &self.light_bind_group_layout,
+            &self.clusters,
+            &self.shadow,
+        );
+    }
+
+    pub fn tone_mapping(&self) -> &ToneMapping {
+        self.hdr.tone_mapping()
+    }
+
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.hdr.set_tone_mapping(&self.queue, tone_mapping);
+    }
+
+    pub fn bloom_settings(&self) -> &BloomSettings {
+        self.bloom.settings()
+    }
+
+    pub fn set_bloom_settings(&mut self, settings: BloomSettings) {
+        self.bloom.set_settings(settings);
+    }
+
+    pub fn auto_exposure(&self) -> Option<&AutoExposureSettings> {
+        self.hdr.auto_exposure()
+    }
+
+    pub fn set_auto_exposure(&mut self, settings: Option<AutoExposureSettings>) {
+        self.hdr
+            .set_auto_exposure(&self.device, &self.queue, settings);
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn supported_sample_counts(&self) -> &[u32] {
+        &self.supported_sample_counts
+    }
+
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        let supported = Self::supported_sample_count(&self.supported_sample_counts, sample_count);
+        if supported != sample_count {
+            log::warn!(
+                "{}x MSAA is not supported, falling back to {}x",
+                sample_count,
+                supported
+            );
+        }
+        if supported == self.sample_count {
+            return;
+        }
+        self.sample_count = supported;
+
+        let (width, height) = self.size();
+        self.hdr.set_sample_count(&self.device, supported);
+        self.depth_texture =
+            Texture::create_depth_texture(&self.device, width, height, supported, "depth_texture");
+        self.post_process.set_depth_view(
+            &self.device,
+            Self::scene_depth_view(supported, &self.depth_texture, &self.depth_prepass),
+        );
+        self.rebuild_scene_pipelines();
+        self.skybox
+            .set_sample_count(&self.device, &self.camera_bind_group_layout, supported);
+    }
+
+    fn rebuild_scene_pipelines(&mut self) {
+        self.pipelines = Self::create_scene_pipelines(
+            &self.device,
+            [
+                &self.texture_bind_group_layout,
+                &self.camera_bind_group_layout,
+                &self.light_bind_group_layout,
+                self.environment.layout(),
+            ],
+            self.clusters.shader_header(),
+            self.debug_view,
+            self.hdr.format(),
+            self.sample_count,
+        );
+    }
+
+    pub fn debug_view(&self) -> DebugView {
+        self.debug_view
+    }
+
+    pub fn set_debug_view(&mut self, debug_view: DebugView) {
+        if debug_view == DebugView::Wireframe
+            && !self
+                .device
+                .features()
+                .contains(wgpu::Features::POLYGON_MODE_LINE)
+        {
+            log::warn!("wireframe debug view requires line polygon mode support");
+            return;
+        }
+        if debug_view == self.debug_view {
+            return;
+        }
+        self.debug_view = debug_view;
+        self.hdr
+            .set_passthrough(&self.queue, debug_view != DebugView::None);
+        self.rebuild_scene_pipelines();
+    }
+
+    pub fn culling_stats(&self) -> CullingStats {
+        self.culling_stats
+    }
+
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
+    pub fn stats_overlay(&self) -> bool {
+        self.stats_overlay
+    }
+
+    pub fn set_stats_overlay(&mut self, stats_overlay: bool) {
+        self.stats_overlay = stats_overlay;
+    }
+
+    pub fn cluster_settings(&self) -> &ClusterSettings {
+        self.clusters.settings()
+    }
+
+    pub fn set_cluster_settings(&mut self, settings: ClusterSettings) {
+        self.clusters.set_settings(&self.device, settings);
+        self.light_bind_group = Self::create_light_bind_group(
+            &self.device,
+            &self.light_bind_group_layout,
+            &self.clusters,
+            &self.shadow,
+        );
+    }
+
+    pub fn clear_color(&self) -> wgpu::Color {
+        self.clear_color
+    }
+
+    pub fn set_clear_color(&mut self, color: wgpu::Color) {
+        self.clear_color = color;
+    }
+
+    pub fn sky(&self) -> &Sky {
+        self.skybox.sky()
+    }
+
+    pub fn set_sky(&mut self, sky: Sky) {
+        self.skybox.set_sky(&self.device, &self.queue, sky);
+    }
+
+    pub fn environment(&self) -> Option<&Arc<Texture>> {
+        self.environment.source()
+    }
+
+    pub fn set_environment(&mut self, cubemap: Option<Arc<Texture>>) {
+        self.environment
+            .set_source(&self.device, &self.queue, cubemap);
+    }
+
+    pub fn ibl_settings(&self) -> &IblSettings {
+        self.environment.settings()
+    }
+
+    pub fn set_ibl_settings(&mut self, settings: IblSettings) {
+        self.environment
+            .set_settings(&self.device, &self.queue, settings);
+    }
+
+    pub fn ssao_settings(&self) -> Option<&SsaoSettings> {
+        self.ssao.as_ref().map(|ssao| ssao.settings())
+    }
+
+    pub fn set_ssao(&mut self, settings: Option<SsaoSettings>) {
+        match (&mut self.ssao, settings) {
+            (Some(ssao), Some(settings)) => ssao.set_settings(settings),
+            (None, Some(settings)) => {
+                let (width, height) = self.size();
+                let ssao = SsaoPipeline::new(
+                    &self.device,
+                    self.depth_prepass.view(),
+                    width,
+                    height,
+                    settings,
+                );
+                self.environment
+                    .set_occlusion(&self.device, Some(ssao.texture()));
+                self.ssao = Some(ssao);
+            }
+            (_, None) => {
+                self.ssao = None;
+                self.environment.set_occlusion(&self.device, None);
+            }
+        }
+    }
+
+    pub fn post_processes(&self) -> impl Iterator<Item = &str> {
+        self.post_process.names()
+    }
+
+    pub fn add_post_process(&mut self, pass: PostProcessPass) -> Result<()> {
+        self.insert_post_process(usize::MAX, pass)
+    }
+
+    pub fn insert_post_process(&mut self, index: usize, pass: PostProcessPass) -> Result<()> {
+        self.post_process.insert(&self.device, index, &pass)
+    }
+
+    pub fn remove_post_process(&mut self, name: &str) -> bool {
+        self.post_process.remove(name)
+    }
+
+    pub fn move_post_process(&mut self, name: &str, index: usize) -> bool {
+        self.post_process.reorder(name, index)
+    }
+
+    pub fn set_post_process_uniforms(&mut self, name: &str, contents: &[u8]) -> bool {
+        self.post_process
+            .set_uniforms(&self.device, &self.queue, name, contents)
+    }
 }
@@ -1,28 +1,438 @@
-use anyhow::Result;
-use cfg_if::cfg_if;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{Result, anyhow, bail};
+use base64::Engine;
 use futures_lite::io::{BufReader, Cursor};
-use glam::{Vec2, Vec3};
+use glam::{Mat3, Mat4, Vec3, Vec4};
 use tobj::LoadError;
 use wgpu::util::DeviceExt;
 
 use crate::{
-    game_object::{Material, Mesh, Model, ModelVertex},
+    assets::{AssetSource, resolve_path},
+    game_object::{
+        Aabb, AlphaMode, Material, MaterialFactors, MaterialTextures, Mesh, Model, ModelVertex,
+        Node,
+    },
     renderer::Renderer,
+    skybox,
     texture::Texture,
 };
 
 pub async fn load_texture(
     filename: &str,
     is_normal_map: bool,
+    assets: &dyn AssetSource,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
 ) -> Result<Texture> {
-    let data = load_binary(filename).await?;
+    let data = load_binary(filename, assets).await?;
     Texture::from_bytes(&data, filename, is_normal_map, device, queue)
 }
 
-pub async fn load_model(filename: &str, renderer: &Renderer) -> Result<Model> {
-    let obj_text = load_string(filename).await?;
+pub async fn load_cubemap(
+    faces: [&str; 6],
+    assets: &dyn AssetSource,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> Result<Texture> {
+    let mut images = Vec::with_capacity(6);
+    for face in faces {
+        images.push(image::load_from_memory(&load_binary(face, assets).await?)?);
+    }
+    let images: [image::DynamicImage; 6] = images.try_into().unwrap();
+    Texture::from_cube_images(&images, Some(faces[0]), device, queue)
+}
+
+pub async fn load_hdr_cubemap(
+    filename: &str,
+    assets: &dyn AssetSource,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> Result<Texture> {
+    let data = load_binary(filename, assets).await?;
+    let image = image::load_from_memory(&data)?.to_rgba32f();
+    Ok(skybox::equirect_to_cubemap(
+        device,
+        queue,
+        &image,
+        Some(filename),
+    ))
+}
+
+fn material_factors(m: &tobj::Material) -> MaterialFactors {
+    let param = |key: &str| -> Option<Vec<f32>> {
+        let values = m.unknown_param.get(key)?;
+        values.split_whitespace().map(|v| v.parse().ok()).collect()
+    };
+
+    let mut factors = MaterialFactors::default();
+    if let Some(diffuse) = m.diffuse {
+        factors.base_color = Vec3::from(diffuse).extend(1.0);
+    }
+    factors.base_color.w = m.dissolve.unwrap_or(1.0);
+    if m.dissolve.is_some_and(|dissolve| dissolve < 1.0) {
+        factors.alpha_mode = AlphaMode::Blend;
+    }
+    // Blender writes Ns = (1 - roughness)^2 * 1000 and Ks = specular
+    if let Some(shininess) = m.shininess {
+        factors.roughness = 1.0 - (shininess / 1000.0).clamp(0.0, 1.0).sqrt();
+    }
+    if let Some(specular) = m.specular {
+        factors.specular = specular[0].clamp(0.0, 1.0);
+    }
+    if let Some(&[r, g, b]) = param("Ke").as_deref() {
+        factors.emissive = Vec3::new(r, g, b);
+    }
+    if let Some(&[roughness]) = param("Pr").as_deref() {
+        factors.roughness = roughness;
+    }
+    if let Some(&[metallic]) = param("Pm").as_deref() {
+        factors.metallic = metallic;
+    }
+    // An alpha map on an otherwise opaque material is almost always a cutout
+    if m.dissolve_texture.is_some() && factors.alpha_mode == AlphaMode::Opaque {
+        factors.alpha_mode = AlphaMode::Mask;
+    }
+    if let Some(&[cutoff]) = param("alpha_cutoff").as_deref() {
+        factors.alpha_mode = AlphaMode::Mask;
+        factors.alpha_cutoff = cutoff;
+    }
+    if let Some(&[double_sided]) = param("double_sided").as_deref() {
+        factors.double_sided = double_sided != 0.0;
+    }
+
+    factors
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NormalMode {
+    #[default]
+    Smooth,
+    Flat,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TexCoordMode {
+    #[default]
+    Planar,
+    Zero,
+}
+
+// How attributes missing from a model file are generated
+#[derive(Clone, Debug, Default)]
+pub struct ImportSettings {
+    pub normals: NormalMode,
+    pub tex_coords: TexCoordMode,
+}
+
+pub async fn load_model(
+    filename: &str,
+    renderer: &Renderer,
+    settings: &ImportSettings,
+    assets: &dyn AssetSource,
+) -> Result<Model> {
+    let extension = std::path::Path::new(filename)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("obj") => load_obj(filename, renderer, settings, assets).await,
+        Some("gltf" | "glb") => load_gltf(filename, renderer, settings, assets).await,
+        _ => Err(anyhow!("unsupported model format: {:?}", filename)),
+    }
+}
+
+fn create_mesh(
+    renderer: &Renderer,
+    name: &str,
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    material: usize,
+) -> Mesh {
+    let vertex_buffer = renderer
+        .device()
+        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+    let index_buffer = renderer
+        .device()
+        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Index Buffer", name)),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+    Mesh::new(
+        name,
+        vertex_buffer,
+        index_buffer,
+        indices.len() as u32,
+        material,
+        Aabb::from_points(vertices.iter().map(|v| Vec3::from(v.position))),
+    )
+}
+
+fn generate_normals(vertices: &mut Vec<ModelVertex>, indices: &mut Vec<u32>, mode: NormalMode) {
+    match mode {
+        NormalMode::Smooth => {
+            // welded by position so texture seams don't split the shading
+            let key = |vertex: &ModelVertex| vertex.position.map(f32::to_bits);
+            let mut normals = HashMap::<[u32; 3], Vec3>::new();
+            for c in indices.chunks(3) {
+                let [p0, p1, p2] =
+                    [c[0], c[1], c[2]].map(|i| Vec3::from(vertices[i as usize].position));
+                // left unnormalized so larger faces contribute more
+                let normal = (p1 - p0).cross(p2 - p0);
+                for &i in c {
+                    *normals.entry(key(&vertices[i as usize])).or_default() += normal;
+                }
+            }
+            for vertex in vertices.iter_mut() {
+                let normal = normals.get(&key(vertex)).copied().unwrap_or_default();
+                vertex.normal = normal.normalize_or(Vec3::Y).into();
+            }
+        }
+        NormalMode::Flat => {
+            *vertices = indices.iter().map(|&i| vertices[i as usize]).collect();
+            *indices = (0..vertices.len() as u32).collect();
+            for triangle in vertices.chunks_mut(3) {
+                let [p0, p1, p2] = [0, 1, 2].map(|i| Vec3::from(triangle[i].position));
+                let normal = (p1 - p0).cross(p2 - p0).normalize_or(Vec3::Y);
+                for vertex in triangle {
+                    vertex.normal = normal.into();
+                }
+            }
+        }
+    }
+}
+
+fn generate_tex_coords(vertices: &mut [ModelVertex], mode: TexCoordMode) {
+    if mode == TexCoordMode::Zero {
+        for vertex in vertices {
+            vertex.tex_coords = [0.0; 2];
+        }
+        return;
+    }
+
+    // project along the thinnest axis of the bounds
+    let bounds = Aabb::from_points(vertices.iter().map(|v| Vec3::from(v.position)));
+    let size = (bounds.max - bounds.min).max(Vec3::splat(f32::EPSILON));
+    let (u, v) = if size.x <= size.y && size.x <= size.z {
+        (2, 1)
+    } else if size.y <= size.z {
+        (0, 2)
+    } else {
+        (0, 1)
+    };
+    for vertex in vertices {
+        let p = (Vec3::from(vertex.position) - bounds.min) / size;
+        vertex.tex_coords = [p[u], 1.0 - p[v]];
+    }
+}
+
+struct TangentGeometry<'a> {
+    vertices: &'a [ModelVertex],
+    indices: &'a [u32],
+    tangents: Vec<[f32; 4]>,
+}
+
+impl TangentGeometry<'_> {
+    fn vertex(&self, face: usize, vert: usize) -> &ModelVertex {
+        &self.vertices[self.indices[face * 3 + vert] as usize]
+    }
+}
+
+impl mikktspace::Geometry for TangentGeometry<'_> {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).position
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).normal
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        // bottom-left origin, as in Blender, so the handedness matches baked normal maps
+        let [u, v] = self.vertex(face, vert).tex_coords;
+        [u, 1.0 - v]
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.tangents[face * 3 + vert] = tangent;
+    }
+}
+
+fn generate_tangents(vertices: &mut Vec<ModelVertex>, indices: &mut [u32]) {
+    let mut geometry = TangentGeometry {
+        vertices,
+        indices,
+        tangents: vec![[0.0; 4]; indices.len()],
+    };
+    if !mikktspace::generate_tangents(&mut geometry) {
+        log::warn!("failed to generate tangents");
+    }
+    let tangents = geometry.tangents;
+
+    // Tangents are generated per corner, so vertices are split where their corners disagree
+    let mut welded = HashMap::new();
+    let mut split = Vec::with_capacity(vertices.len());
+    for (index, tangent) in indices.iter_mut().zip(tangents) {
+        let mut vertex = vertices[*index as usize];
+        let normal = Vec3::from(vertex.normal);
+        let direction = Vec3::new(tangent[0], tangent[1], tangent[2]);
+        vertex.tangent = if direction.is_finite() && direction.length_squared() > 0.0 {
+            tangent
+        } else {
+            // no usable texture coordinates, so there is no normal map to orient
+            normal.any_orthonormal_vector().extend(1.0).into()
+        };
+        *index = *welded
+            .entry((*index, vertex.tangent.map(f32::to_bits)))
+            .or_insert_with(|| {
+                split.push(vertex);
+                split.len() as u32 - 1
+            });
+    }
+    *vertices = split;
+}
+
+// The shader only reads alpha from the base color, so a separate map_d is
+// folded into it. Exporters often point map_d at the diffuse image itself, so
+// its alpha channel is used when it has one, otherwise its luminance
+async fn load_base_color_with_alpha(
+    diffuse: Option<&str>,
+    dissolve: &str,
+    assets: &dyn AssetSource,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> Result<Texture> {
+    let dissolve_image = image::load_from_memory(&load_binary(dissolve, assets).await?)?;
+    let alpha = if dissolve_image.color().has_alpha() {
+        let rgba = dissolve_image.to_rgba8();
+        image::GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+            image::Luma([rgba[(x, y)][3]])
+        })
+    } else {
+        dissolve_image.to_luma8()
+    };
+
+    let mut base_color = match diffuse {
+        Some(filename) => {
+            image::load_from_memory(&load_binary(filename, assets).await?)?.to_rgba8()
+        }
+        None => image::RgbaImage::from_pixel(alpha.width(), alpha.height(), image::Rgba([255; 4])),
+    };
+    let alpha = if alpha.dimensions() == base_color.dimensions() {
+        alpha
+    } else {
+        image::imageops::resize(
+            &alpha,
+            base_color.width(),
+            base_color.height(),
+            image::imageops::FilterType::Triangle,
+        )
+    };
+    for (pixel, alpha) in base_color.pixels_mut().zip(alpha.pixels()) {
+        pixel[3] = (pixel[3] as u16 * alpha[0] as u16 / 255) as u8;
+    }
+
+    Texture::from_image(
+        &base_color.into(),
+        Some(diffuse.unwrap_or(dissolve)),
+        false,
+        device,
+        queue,
+    )
+}
+
+// MTL keeps roughness and metallic in separate greyscale maps, the shader
+// expects them packed like glTF: roughness in green, metallic in blue
+async fn load_metallic_roughness(
+    roughness: Option<&str>,
+    metallic: Option<&str>,
+    assets: &dyn AssetSource,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> Result<Texture> {
+    let mut maps = [None, None];
+    for (map, filename) in maps.iter_mut().zip([roughness, metallic]) {
+        if let Some(filename) = filename {
+            let data = load_binary(filename, assets).await?;
+            *map = Some(image::load_from_memory(&data)?.to_luma8());
+        }
+    }
+    let (width, height) = maps
+        .iter()
+        .flatten()
+        .map(|map| map.dimensions())
+        .fold((1, 1), |(w, h), (mw, mh)| (w.max(mw), h.max(mh)));
+    let [roughness, metallic] = maps.map(|map| {
+        map.map(|map| {
+            if map.dimensions() == (width, height) {
+                map
+            } else {
+                image::imageops::resize(&map, width, height, image::imageops::FilterType::Triangle)
+            }
+        })
+    });
+
+    let packed = image::RgbaImage::from_fn(width, height, |x, y| {
+        let sample =
+            |map: &Option<image::GrayImage>| map.as_ref().map_or(255, |map| map[(x, y)][0]);
+        image::Rgba([255, sample(&roughness), sample(&metallic), 255])
+    });
+    Texture::from_image(
+        &packed.into(),
+        Some("metallic_roughness"),
+        true,
+        device,
+        queue,
+    )
+}
+
+// Texture paths are relative to the MTL file, not the OBJ
+fn resolve_texture_paths(mtl_path: &str, materials: &mut [tobj::Material]) -> Result<()> {
+    for m in materials {
+        for texture in [
+            &mut m.ambient_texture,
+            &mut m.diffuse_texture,
+            &mut m.specular_texture,
+            &mut m.normal_texture,
+            &mut m.shininess_texture,
+            &mut m.dissolve_texture,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *texture = resolve_path(mtl_path, texture)?;
+        }
+        for key in ["map_Ke", "map_Pr", "map_Pm"] {
+            if let Some(texture) = m.unknown_param.get_mut(key) {
+                *texture = resolve_path(mtl_path, texture)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn load_obj(
+    filename: &str,
+    renderer: &Renderer,
+    settings: &ImportSettings,
+    assets: &dyn AssetSource,
+) -> Result<Model> {
+    let obj_text = load_string(filename, assets).await?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
 
@@ -36,140 +446,142 @@ pub async fn load_model(filename: &str, renderer: &Renderer) -> Result<Model> {
         |p| async move {
             match p.to_str() {
                 Some(path) => {
-                    let mat_text = load_string(path)
-                        .await
-                        .map_err(|_| LoadError::GenericFailure)?;
-                    tobj::futures::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text))).await
+                    let failed = |err: anyhow::Error| {
+                        log::warn!("{}", err);
+                        LoadError::GenericFailure
+                    };
+                    let path = resolve_path(filename, path).map_err(failed)?;
+                    let mat_text = load_string(&path, assets).await.map_err(failed)?;
+                    let (mut materials, names) =
+                        tobj::futures::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+                            .await?;
+                    resolve_texture_paths(&path, &mut materials).map_err(failed)?;
+                    Ok((materials, names))
                 }
                 None => Ok(Default::default()),
             }
         },
     )
     .await?;
+    let obj_materials = obj_materials.unwrap_or_else(|err| {
+        log::warn!("failed to load materials for {:?}: {}", filename, err);
+        Vec::new()
+    });
 
     let mut max_mat_id = 0;
-    let meshes = models
-        .into_iter()
-        .map(|m| {
-            let mut vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| ModelVertex {
-                    position: [
-                        m.mesh.positions[i * 3],
-                        m.mesh.positions[i * 3 + 1],
-                        m.mesh.positions[i * 3 + 2],
-                    ],
-                    tex_coords: [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
-                    tangent: [0.0; 3],
-                    bitangent: [0.0; 3],
-                })
-                .collect::<Vec<ModelVertex>>();
-
-            let indices = &m.mesh.indices;
-            let mut triangles_included = vec![0; vertices.len()];
+    let mut meshes = Vec::with_capacity(models.len());
+    for m in models {
+        let num_vertices = m.mesh.positions.len() / 3;
+        if let Some(&i) = m.mesh.indices.iter().find(|&&i| i as usize >= num_vertices) {
+            bail!(
+                "{:?} in {:?} references missing vertex {}",
+                m.name,
+                filename,
+                i
+            );
+        }
 
-            for c in indices.chunks(3) {
-                let v0 = vertices[c[0] as usize];
-                let v1 = vertices[c[1] as usize];
-                let v2 = vertices[c[2] as usize];
-
-                let pos0: Vec3 = v0.position.into();
-                let pos1: Vec3 = v1.position.into();
-                let pos2: Vec3 = v2.position.into();
-
-                let uv0: Vec2 = v0.tex_coords.into();
-                let uv1: Vec2 = v1.tex_coords.into();
-                let uv2: Vec2 = v2.tex_coords.into();
-
-                let delta_pos1 = pos1 - pos0;
-                let delta_pos2 = pos2 - pos0;
-
-                let delta_uv1 = uv1 - uv0;
-                let delta_uv2 = uv2 - uv0;
-
-                let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
-                let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
-                let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
-
-                vertices[c[0] as usize].tangent =
-                    (tangent + Vec3::from(vertices[c[0] as usize].tangent)).into();
-                vertices[c[1] as usize].tangent =
-                    (tangent + Vec3::from(vertices[c[1] as usize].tangent)).into();
-                vertices[c[2] as usize].tangent =
-                    (tangent + Vec3::from(vertices[c[2] as usize].tangent)).into();
-                vertices[c[0] as usize].bitangent =
-                    (bitangent + Vec3::from(vertices[c[0] as usize].bitangent)).into();
-                vertices[c[1] as usize].bitangent =
-                    (bitangent + Vec3::from(vertices[c[1] as usize].bitangent)).into();
-                vertices[c[2] as usize].bitangent =
-                    (bitangent + Vec3::from(vertices[c[2] as usize].bitangent)).into();
-
-                triangles_included[c[0] as usize] += 1;
-                triangles_included[c[1] as usize] += 1;
-                triangles_included[c[2] as usize] += 1;
+        let mut vertices = m
+            .mesh
+            .positions
+            .chunks_exact(3)
+            .map(|p| ModelVertex {
+                position: [p[0], p[1], p[2]],
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let mut indices = m.mesh.indices;
+
+        if m.mesh.texcoords.len() == num_vertices * 2 {
+            for (v, uv) in vertices.iter_mut().zip(m.mesh.texcoords.chunks_exact(2)) {
+                v.tex_coords = [uv[0], 1.0 - uv[1]];
             }
+        } else {
+            log::warn!(
+                "{:?} in {:?} has no texture coordinates, generating them",
+                m.name,
+                filename
+            );
+            generate_tex_coords(&mut vertices, settings.tex_coords);
+        }
 
-            for (i, n) in triangles_included.into_iter().enumerate() {
-                let denom = 1.0 / n as f32;
-                let v = &mut vertices[i];
-                v.tangent = (Vec3::from(v.tangent) * denom).into();
-                v.bitangent = (Vec3::from(v.bitangent) * denom).into();
+        if m.mesh.normals.len() == num_vertices * 3 {
+            for (v, n) in vertices.iter_mut().zip(m.mesh.normals.chunks_exact(3)) {
+                v.normal = [n[0], n[1], n[2]];
             }
+        } else {
+            log::warn!(
+                "{:?} in {:?} has no normals, generating them",
+                m.name,
+                filename
+            );
+            generate_normals(&mut vertices, &mut indices, settings.normals);
+        }
 
-            let vertex_buffer =
-                renderer
-                    .device()
-                    .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                        label: Some(&format!("{:?} Vertex Buffer", filename)),
-                        contents: bytemuck::cast_slice(&vertices),
-                        usage: wgpu::BufferUsages::VERTEX,
-                    });
-            let index_buffer =
-                renderer
-                    .device()
-                    .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                        label: Some(&format!("{:?} Index Buffer", filename)),
-                        contents: bytemuck::cast_slice(&m.mesh.indices),
-                        usage: wgpu::BufferUsages::INDEX,
-                    });
-            let material_id = std::cmp::max(max_mat_id, m.mesh.material_id.unwrap_or(0));
-            max_mat_id = material_id;
-
-            Mesh::new(
-                filename,
-                vertex_buffer,
-                index_buffer,
-                m.mesh.indices.len() as u32,
-                material_id,
-            )
-        })
-        .collect();
+        generate_tangents(&mut vertices, &mut indices);
+
+        let material_id = m.mesh.material_id.unwrap_or(0);
+        max_mat_id = max_mat_id.max(material_id);
+
+        meshes.push(create_mesh(
+            renderer,
+            filename,
+            &vertices,
+            &indices,
+            material_id,
+        ));
+    }
 
     let mut materials = Vec::new();
-    for m in obj_materials? {
-        let diffuse_texture = match m.diffuse_texture.as_ref() {
-            Some(filename) => {
-                load_texture(filename, false, renderer.device(), renderer.queue()).await
-            }
-            None => Texture::default(false, renderer.device(), renderer.queue()),
-        }?;
+    for m in obj_materials {
+        let device = renderer.device();
+        let queue = renderer.queue();
+        let mut textures = MaterialTextures::default(device, queue);
 
-        let normal_texture = match m.normal_texture.as_ref() {
-            Some(filename) => {
-                load_texture(filename, true, renderer.device(), renderer.queue()).await
-            }
-            None => Texture::default(true, renderer.device(), renderer.queue()),
-        }?;
+        if let Some(filename) = &m.dissolve_texture {
+            textures.base_color = load_base_color_with_alpha(
+                m.diffuse_texture.as_deref(),
+                filename,
+                assets,
+                device,
+                queue,
+            )
+            .await?
+            .into();
+        } else if let Some(filename) = &m.diffuse_texture {
+            textures.base_color = load_texture(filename, false, assets, device, queue)
+                .await?
+                .into();
+        }
+        if let Some(filename) = &m.normal_texture {
+            textures.normal = load_texture(filename, true, assets, device, queue)
+                .await?
+                .into();
+        }
+        if let Some(filename) = m.unknown_param.get("map_Ke") {
+            textures.emissive = load_texture(filename, false, assets, device, queue)
+                .await?
+                .into();
+        }
+        if let Some(filename) = &m.ambient_texture {
+            textures.occlusion = load_texture(filename, true, assets, device, queue)
+                .await?
+                .into();
+        }
+        let roughness = m.unknown_param.get("map_Pr").map(String::as_str);
+        let metallic = m.unknown_param.get("map_Pm").map(String::as_str);
+        if roughness.is_some() || metallic.is_some() {
+            textures.metallic_roughness =
+                load_metallic_roughness(roughness, metallic, assets, device, queue)
+                    .await?
+                    .into();
+        }
 
         materials.push(Material::new(
-            renderer.device(),
+            device,
             &m.name,
-            diffuse_texture,
-            normal_texture,
+            textures,
+            material_factors(&m),
             renderer.texture_bind_group_layout(),
         ));
     }
@@ -178,8 +590,8 @@ pub async fn load_model(filename: &str, renderer: &Renderer) -> Result<Model> {
         materials.push(Material::new(
             renderer.device(),
             "default",
-            Texture::default(false, renderer.device(), renderer.queue()).unwrap(),
-            Texture::default(true, renderer.device(), renderer.queue()).unwrap(),
+            MaterialTextures::default(renderer.device(), renderer.queue()),
+            MaterialFactors::default(),
             renderer.texture_bind_group_layout(),
         ));
     }
@@ -187,104 +599,533 @@ pub async fn load_model(filename: &str, renderer: &Renderer) -> Result<Model> {
     Ok(Model::new(filename, meshes, materials))
 }
 
-#[cfg(target_arch = "wasm32")]
-use std::sync::OnceLock;
+async fn load_uri(base: &str, uri: &str, assets: &dyn AssetSource) -> Result<Vec<u8>> {
+    match uri.strip_prefix("data:") {
+        Some(data) => {
+            let Some((header, payload)) = data.split_once(',') else {
+                bail!("malformed data URI");
+            };
+            if !header.ends_with(";base64") {
+                bail!("unsupported data URI encoding: {:?}", header);
+            }
+            Ok(base64::engine::general_purpose::STANDARD.decode(payload)?)
+        }
+        None => {
+            let path = percent_encoding::percent_decode_str(uri).decode_utf8()?;
+            load_binary(&format!("{}{}", base, path), assets).await
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn load_gltf_texture(
+    texture: gltf::Texture<'_>,
+    tex_coord: u32,
+    is_normal_map: bool,
+    base: &str,
+    buffers: &[Vec<u8>],
+    renderer: &Renderer,
+    assets: &dyn AssetSource,
+    cache: &mut HashMap<(usize, bool), Arc<Texture>>,
+) -> Result<Arc<Texture>> {
+    if tex_coord != 0 {
+        log::warn!(
+            "texture {} uses TEXCOORD_{}, only TEXCOORD_0 is supported",
+            texture.index(),
+            tex_coord
+        );
+    }
+    // normal maps are created linear, so they're cached apart from sRGB uses
+    if let Some(texture) = cache.get(&(texture.index(), is_normal_map)) {
+        return Ok(texture.clone());
+    }
 
-#[cfg(target_arch = "wasm32")]
-static BASE_URL: OnceLock<String> = OnceLock::new();
+    let image = texture.source();
+    let label = image.name().unwrap_or("glTF image");
+    let loaded;
+    let data = match image.source() {
+        gltf::image::Source::View { view, .. } => buffers[view.buffer().index()]
+            .get(view.offset()..view.offset() + view.length())
+            .ok_or_else(|| anyhow!("image {} is out of bounds", image.index()))?,
+        gltf::image::Source::Uri { uri, .. } => {
+            loaded = load_uri(base, uri, assets).await?;
+            &loaded
+        }
+    };
+    let created = Arc::new(Texture::from_bytes(
+        data,
+        label,
+        is_normal_map,
+        renderer.device(),
+        renderer.queue(),
+    )?);
+    cache.insert((texture.index(), is_normal_map), created.clone());
+    Ok(created)
+}
 
-#[cfg(target_arch = "wasm32")]
-pub fn set_base_url(url: &str) -> Result<(), &'static str> {
-    match BASE_URL.set(url.to_string()) {
-        Ok(_) => Ok(()),
-        Err(_) => Err("Base URL has already been set"),
+async fn load_gltf_material(
+    material: gltf::Material<'_>,
+    base: &str,
+    buffers: &[Vec<u8>],
+    renderer: &Renderer,
+    assets: &dyn AssetSource,
+    cache: &mut HashMap<(usize, bool), Arc<Texture>>,
+) -> Result<Material> {
+    let pbr = material.pbr_metallic_roughness();
+    let mut factors = MaterialFactors {
+        base_color: Vec4::from(pbr.base_color_factor()),
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        emissive: Vec3::from(material.emissive_factor()),
+        alpha_mode: match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        },
+        alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+        double_sided: material.double_sided(),
+        ..Default::default()
+    };
+
+    let mut textures = MaterialTextures::default(renderer.device(), renderer.queue());
+    if let Some(info) = pbr.base_color_texture() {
+        textures.base_color = load_gltf_texture(
+            info.texture(),
+            info.tex_coord(),
+            false,
+            base,
+            buffers,
+            renderer,
+            assets,
+            cache,
+        )
+        .await?;
+    }
+    if let Some(info) = pbr.metallic_roughness_texture() {
+        textures.metallic_roughness = load_gltf_texture(
+            info.texture(),
+            info.tex_coord(),
+            true,
+            base,
+            buffers,
+            renderer,
+            assets,
+            cache,
+        )
+        .await?;
+    }
+    if let Some(normal) = material.normal_texture() {
+        factors.normal_scale = normal.scale();
+        textures.normal = load_gltf_texture(
+            normal.texture(),
+            normal.tex_coord(),
+            true,
+            base,
+            buffers,
+            renderer,
+            assets,
+            cache,
+        )
+        .await?;
     }
+    if let Some(occlusion) = material.occlusion_texture() {
+        factors.occlusion_strength = occlusion.strength();
+        textures.occlusion = load_gltf_texture(
+            occlusion.texture(),
+            occlusion.tex_coord(),
+            true,
+            base,
+            buffers,
+            renderer,
+            assets,
+            cache,
+        )
+        .await?;
+    }
+    if let Some(info) = material.emissive_texture() {
+        textures.emissive = load_gltf_texture(
+            info.texture(),
+            info.tex_coord(),
+            false,
+            base,
+            buffers,
+            renderer,
+            assets,
+            cache,
+        )
+        .await?;
+    }
+
+    Ok(Material::new(
+        renderer.device(),
+        material.name().unwrap_or("glTF material"),
+        textures,
+        factors,
+        renderer.texture_bind_group_layout(),
+    ))
 }
 
-#[cfg(target_arch = "wasm32")]
-async fn js_fetch(filename: &str) -> Result<web_sys::Response> {
-    use wasm_bindgen::JsCast;
-    use wasm_bindgen_futures::JsFuture;
-    use web_sys::js_sys;
+fn load_gltf_primitive(
+    primitive: gltf::Primitive<'_>,
+    name: &str,
+    mirrored: bool,
+    buffers: &[Vec<u8>],
+    default_material: usize,
+    renderer: &Renderer,
+    settings: &ImportSettings,
+) -> Result<Option<Mesh>> {
+    if primitive.mode() != gltf::mesh::Mode::Triangles {
+        log::warn!(
+            "skipping {:?} primitive in {:?}, only triangles are supported",
+            primitive.mode(),
+            name
+        );
+        return Ok(None);
+    }
+
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+    let Some(positions) = reader.read_positions() else {
+        bail!("primitive in {:?} has no positions", name);
+    };
+    let mut vertices = positions
+        .map(|position| ModelVertex {
+            position,
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    match reader.read_tex_coords(0) {
+        Some(tex_coords) => {
+            for (vertex, tex_coords) in vertices.iter_mut().zip(tex_coords.into_f32()) {
+                vertex.tex_coords = tex_coords;
+            }
+        }
+        None => generate_tex_coords(&mut vertices, settings.tex_coords),
+    }
+
+    let mut indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+        None => (0..vertices.len() as u32).collect(),
+    };
+    if indices.len() % 3 != 0 || indices.iter().any(|&i| i as usize >= vertices.len()) {
+        bail!("primitive in {:?} has invalid indices", name);
+    }
 
-    let url = match BASE_URL.get() {
-        Some(base) => format!("{}/{}", base, filename),
-        None => format!("http://localhost:8080/models/{}", filename),
+    // Generated normals reorder the vertices, and the spec says to ignore the
+    // file's tangents in that case anyway
+    let tangents = match reader.read_normals() {
+        Some(normals) => {
+            for (vertex, normal) in vertices.iter_mut().zip(normals) {
+                vertex.normal = normal;
+            }
+            reader.read_tangents()
+        }
+        None => {
+            log::warn!("primitive in {:?} has no normals, generating them", name);
+            generate_normals(&mut vertices, &mut indices, settings.normals);
+            None
+        }
     };
+    match tangents {
+        Some(tangents) => {
+            for (vertex, tangent) in vertices.iter_mut().zip(tangents) {
+                vertex.tangent = tangent;
+            }
+        }
+        None => generate_tangents(&mut vertices, &mut indices),
+    }
 
-    let global = js_sys::global();
+    // Back faces are culled, so nodes that mirror the mesh need the opposite
+    // winding. The shader already flips the tangent handedness
+    if mirrored {
+        for triangle in indices.chunks_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
 
-    let fetch_fn = js_sys::Function::from(
-        js_sys::Reflect::get(&global, &"fetch".into())
-            .map_err(|_| anyhow::anyhow!("fetch not found in global scope"))?,
-    );
+    Ok(Some(create_mesh(
+        renderer,
+        name,
+        &vertices,
+        &indices,
+        primitive.material().index().unwrap_or(default_material),
+    )))
+}
 
-    let resp_value = JsFuture::from(
-        fetch_fn
-            .call1(&global, &url.into())
-            .map_err(|e| anyhow::anyhow!("JS fetch call error: {:?}", e))?
-            .dyn_into::<js_sys::Promise>()
-            .map_err(|_| anyhow::anyhow!("JS fetch did not return a Promise"))?,
-    )
-    .await
-    .map_err(|e| anyhow::anyhow!("JS fetch error: {:?}", e))?;
+async fn load_gltf(
+    filename: &str,
+    renderer: &Renderer,
+    settings: &ImportSettings,
+    assets: &dyn AssetSource,
+) -> Result<Model> {
+    let data = load_binary(filename, assets).await?;
+    let gltf::Gltf { document, mut blob } = gltf::Gltf::from_slice(&data)?;
+    let base = filename.rfind('/').map_or("", |i| &filename[..=i]);
 
-    let resp: web_sys::Response = resp_value
-        .dyn_into()
-        .map_err(|_| anyhow::anyhow!("JS fetch not a Response"))?;
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => blob
+                .take()
+                .ok_or_else(|| anyhow!("{:?} has no binary chunk", filename))?,
+            gltf::buffer::Source::Uri(uri) => load_uri(base, uri, assets).await?,
+        };
+        if data.len() < buffer.length() {
+            bail!("buffer {} of {:?} is truncated", buffer.index(), filename);
+        }
+        buffers.push(data);
+    }
 
-    if !resp.ok() {
-        return Err(anyhow::anyhow!("JS fetch HTTP error: {}", resp.status()));
+    let mut materials = Vec::new();
+    let mut textures = HashMap::new();
+    for material in document.materials() {
+        materials.push(
+            load_gltf_material(material, base, &buffers, renderer, assets, &mut textures).await?,
+        );
+    }
+    let default_material = materials.len();
+
+    let roots = match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(scene) => scene.nodes().collect::<Vec<_>>(),
+        None => {
+            let children = document
+                .nodes()
+                .flat_map(|node| node.children().map(|child| child.index()))
+                .collect::<Vec<_>>();
+            document
+                .nodes()
+                .filter(|node| !children.contains(&node.index()))
+                .collect()
+        }
+    };
+
+    let mut meshes = Vec::new();
+    let mut mesh_ranges = HashMap::<_, std::ops::Range<usize>>::new();
+    let mut nodes = Vec::new();
+    let mut visited = vec![false; document.nodes().len()];
+    let mut stack = roots
+        .into_iter()
+        .rev()
+        .map(|node| (node, None, Mat4::IDENTITY))
+        .collect::<Vec<_>>();
+    while let Some((node, parent, parent_world)) = stack.pop() {
+        if std::mem::replace(&mut visited[node.index()], true) {
+            bail!(
+                "node {} of {:?} has more than one parent",
+                node.index(),
+                filename
+            );
+        }
+
+        let transform = Mat4::from_cols_array_2d(&node.transform().matrix());
+        let world = parent_world * transform;
+        let range = match node.mesh() {
+            // each mesh is uploaded once per handedness of the nodes using it
+            Some(mesh) => {
+                let key = (mesh.index(), Mat3::from_mat4(world).determinant() < 0.0);
+                if let Some(range) = mesh_ranges.get(&key) {
+                    range.clone()
+                } else {
+                    let start = meshes.len();
+                    let name = mesh.name().unwrap_or(filename);
+                    for primitive in mesh.primitives() {
+                        meshes.extend(load_gltf_primitive(
+                            primitive,
+                            name,
+                            key.1,
+                            &buffers,
+                            default_material,
+                            renderer,
+                            settings,
+                        )?);
+                    }
+                    mesh_ranges.insert(key, start..meshes.len());
+                    start..meshes.len()
+                }
+            }
+            None => 0..0,
+        };
+
+        let index = nodes.len();
+        nodes.push(Node {
+            name: node
+                .name()
+                .map_or_else(|| format!("node {}", node.index()), str::to_string),
+            transform,
+            parent,
+            meshes: range,
+        });
+        stack.extend(
+            node.children()
+                .collect::<Vec<_>>()
+                .into_iter()
+                .rev()
+                .map(|child| (child, Some(index), world)),
+        );
+    }
+
+    if meshes.iter().any(|mesh| mesh.material == default_material) {
+        materials.push(Material::new(
+            renderer.device(),
+            "default",
+            MaterialTextures::default(renderer.device(), renderer.queue()),
+            MaterialFactors::default(),
+            renderer.texture_bind_group_layout(),
+        ));
     }
 
-    Ok(resp)
+    let mut model = Model::new(filename, meshes, materials);
+    model.nodes = nodes;
+    model.update_bounds();
+    Ok(model)
+}
+
+pub async fn load_string(filename: &str, assets: &dyn AssetSource) -> Result<String> {
+    let data = assets.load(filename).await?;
+    String::from_utf8(data).map_err(|_| anyhow!("{:?} is not valid UTF-8", filename))
 }
 
-pub async fn load_string(filename: &str) -> Result<String> {
-    cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            use wasm_bindgen_futures::JsFuture;
+pub async fn load_binary(filename: &str, assets: &dyn AssetSource) -> Result<Vec<u8>> {
+    assets.load(filename).await
+}
 
-            let resp = js_fetch(filename).await?;
-            let text = JsFuture::from(resp.text()
-                .map_err(|e| anyhow::anyhow!("JS load text error: {:?}", e))?)
-                .await
-                .map_err(|e| anyhow::anyhow!("JS await error: {:?}", e))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            let txt = text.as_string()
-                .ok_or_else(|| anyhow::anyhow!("JS response not a string"))?;
-        } else {
-            let path = std::path::Path::new(&std::env::var("OUT_DIR").unwrap())
-                .join("models")
-                .join(filename);
-            let txt = async_fs::read_to_string(path).await?;
+    fn vertex(position: [f32; 3], tex_coords: [f32; 2]) -> ModelVertex {
+        ModelVertex {
+            position,
+            tex_coords,
+            ..Default::default()
         }
     }
 
-    Ok(txt)
-}
+    // Two faces meeting at a ridge along +Z, sloping down to -X and +X
+    const RIDGE: [[f32; 3]; 4] = [
+        [-1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [0.0, 1.0, 1.0],
+        [1.0, 0.0, 0.0],
+    ];
+
+    #[test]
+    fn smooth_normals_weld_seam_vertices() {
+        // the ridge vertices are split by a texture seam
+        let [l, a, b, r] = RIDGE;
+        let mut vertices = vec![
+            vertex(l, [0.0, 0.0]),
+            vertex(b, [0.0, 0.0]),
+            vertex(a, [0.0, 0.0]),
+            vertex(r, [1.0, 1.0]),
+            vertex(a, [1.0, 1.0]),
+            vertex(b, [1.0, 1.0]),
+        ];
+        let mut indices = vec![0, 1, 2, 3, 4, 5];
+        generate_normals(&mut vertices, &mut indices, NormalMode::Smooth);
 
-pub async fn load_binary(filename: &str) -> Result<Vec<u8>> {
-    cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            use web_sys::js_sys;
-            use wasm_bindgen_futures::JsFuture;
+        assert_eq!(indices, [0, 1, 2, 3, 4, 5]);
+        assert_eq!(vertices[2].normal, vertices[4].normal);
+        assert_eq!(vertices[1].normal, vertices[5].normal);
+        assert!(Vec3::from(vertices[2].normal).abs_diff_eq(Vec3::Y, 1e-6));
+        let left = Vec3::new(-1.0, 1.0, 0.0).normalize();
+        assert!(Vec3::from(vertices[0].normal).abs_diff_eq(left, 1e-6));
+    }
 
-            let resp = js_fetch(filename).await?;
-            let array_buffer = JsFuture::from(resp.array_buffer()
-                .map_err(|e| anyhow::anyhow!("JS load buffer error: {:?}", e))?)
-                .await
-                .map_err(|e| anyhow::anyhow!("JS await error: {:?}", e))?;
+    #[test]
+    fn flat_normals_deindex_to_face_normals() {
+        let mut vertices = RIDGE.map(|p| vertex(p, [0.0, 0.0])).to_vec();
+        let mut indices = vec![0, 2, 1, 3, 1, 2];
+        generate_normals(&mut vertices, &mut indices, NormalMode::Flat);
 
-            let uint8_array = js_sys::Uint8Array::new(&array_buffer);
-            let data = uint8_array.to_vec();
-        } else {
-            let path = std::path::Path::new(&std::env::var("OUT_DIR").unwrap())
-                .join("models")
-                .join(filename);
-            let data = async_fs::read(path).await?;
+        assert_eq!(indices, (0..6).collect::<Vec<_>>());
+        assert_eq!(vertices.len(), 6);
+        for triangle in vertices.chunks(3) {
+            let [p0, p1, p2] = [0, 1, 2].map(|i| Vec3::from(triangle[i].position));
+            let face = (p1 - p0).cross(p2 - p0).normalize();
+            for vertex in triangle {
+                assert!(Vec3::from(vertex.normal).abs_diff_eq(face, 1e-6));
+            }
         }
+        let right = Vec3::new(1.0, 1.0, 0.0).normalize();
+        assert!(Vec3::from(vertices[3].normal).abs_diff_eq(right, 1e-6));
     }
 
-    Ok(data)
+    #[test]
+    fn planar_tex_coords_project_along_the_thinnest_axis() {
+        // Y is the thinnest axis, so u follows X and v follows Z
+        let mut vertices = vec![
+            vertex([2.0, -1.0, 0.0], [9.0, 9.0]),
+            vertex([6.0, -0.5, 3.0], [9.0, 9.0]),
+            vertex([4.0, -0.75, 1.5], [9.0, 9.0]),
+        ];
+        generate_tex_coords(&mut vertices, TexCoordMode::Planar);
+
+        let tex_coords = vertices.iter().map(|v| v.tex_coords).collect::<Vec<_>>();
+        assert_eq!(tex_coords, [[0.0, 1.0], [1.0, 0.0], [0.5, 0.5]]);
+    }
+
+    #[test]
+    fn degenerate_geometry_generates_finite_attributes() {
+        let point = [1.0, 2.0, 3.0];
+        let line = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [2.0, 0.0, 0.0]];
+        for positions in [[point; 3], line] {
+            for mode in [NormalMode::Smooth, NormalMode::Flat] {
+                let mut vertices = positions.map(|p| vertex(p, [0.0, 0.0])).to_vec();
+                let mut indices = vec![0, 1, 2];
+                generate_normals(&mut vertices, &mut indices, mode);
+                generate_tex_coords(&mut vertices, TexCoordMode::Planar);
+                for vertex in &vertices {
+                    assert!(Vec3::from(vertex.normal).is_normalized());
+                    assert!(vertex.tex_coords.iter().all(|c| c.is_finite()));
+                }
+            }
+        }
+
+        generate_tex_coords(&mut [], TexCoordMode::Planar);
+    }
+
+    #[test]
+    fn dissolve_sets_alpha_with_or_without_kd() {
+        let mtl = "newmtl tinted\nKd 0.2 0.4 0.6\nd 0.5\n\nnewmtl plain\nd 0.25\n\nnewmtl opaque\nKd 1 1 1\n";
+        let (materials, _) =
+            tobj::load_mtl_buf(&mut std::io::BufReader::new(mtl.as_bytes())).unwrap();
+        let factors: Vec<_> = materials.iter().map(material_factors).collect();
+
+        assert_eq!(factors[0].base_color, Vec4::new(0.2, 0.4, 0.6, 0.5));
+        assert_eq!(factors[0].alpha_mode, AlphaMode::Blend);
+        assert_eq!(factors[1].base_color, Vec4::new(1.0, 1.0, 1.0, 0.25));
+        assert_eq!(factors[1].alpha_mode, AlphaMode::Blend);
+        assert_eq!(factors[2].base_color.w, 1.0);
+        assert_eq!(factors[2].alpha_mode, AlphaMode::Opaque);
+    }
+
+    #[test]
+    fn mtl_textures_resolve_against_the_mtl() {
+        let mtl = "newmtl a\nmap_Kd ../../textures/wood/../bark.png\nmap_Bump normals\\bark_n.png\nmap_Pr ./rough.png\n";
+        let (mut materials, _) =
+            tobj::load_mtl_buf(&mut std::io::BufReader::new(mtl.as_bytes())).unwrap();
+        resolve_texture_paths("pack/models/trees/bark.mtl", &mut materials).unwrap();
+
+        let m = &materials[0];
+        assert_eq!(m.diffuse_texture.as_deref(), Some("pack/textures/bark.png"));
+        assert_eq!(
+            m.normal_texture.as_deref(),
+            Some("pack/models/trees/normals/bark_n.png")
+        );
+        assert_eq!(
+            m.unknown_param.get("map_Pr").map(String::as_str),
+            Some("pack/models/trees/rough.png")
+        );
+    }
+
+    #[test]
+    fn mtl_textures_outside_the_root_are_rejected() {
+        let mtl = "newmtl a\nmap_Kd ../../bark.png\n";
+        let (mut materials, _) =
+            tobj::load_mtl_buf(&mut std::io::BufReader::new(mtl.as_bytes())).unwrap();
+        assert!(resolve_texture_paths("models/bark.mtl", &mut materials).is_err());
+    }
 }
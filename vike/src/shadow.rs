@@ -0,0 +1,286 @@
+use std::f32::consts::FRAC_PI_2;
+use std::ops::Range;
+use std::sync::Arc;
+
+use glam::{Mat4, Vec3};
+
+use crate::{
+    depth::DepthPipelines, game_object::Model, instance::InstanceBuffer, stats::FrameStats,
+    texture::Texture,
+};
+
+const CUBE_FACES: [(Vec3, Vec3); 6] = [
+    (Vec3::X, Vec3::NEG_Y),
+    (Vec3::NEG_X, Vec3::NEG_Y),
+    (Vec3::Y, Vec3::Z),
+    (Vec3::NEG_Y, Vec3::NEG_Z),
+    (Vec3::Z, Vec3::NEG_Y),
+    (Vec3::NEG_Z, Vec3::NEG_Y),
+];
+
+#[derive(Clone, Debug)]
+pub struct ShadowSettings {
+    pub resolution: u32,
+    pub depth_bias: f32,
+    pub max_shadow_maps: u32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 512,
+            depth_bias: 0.05,
+            max_shadow_maps: 4,
+            near: 0.1,
+            far: 100.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadowUniform {
+    depth_bias: f32,
+    near: f32,
+    far: f32,
+    num_shadows: u32,
+    tiles_per_row: u32,
+    tile_size: f32,
+    texel_size: [f32; 2],
+}
+
+pub struct ShadowPipeline {
+    pipelines: DepthPipelines,
+    face_layout: wgpu::BindGroupLayout,
+    face_buffer: wgpu::Buffer,
+    face_bind_group: wgpu::BindGroup,
+    face_stride: u32,
+    uniform: ShadowUniform,
+    uniform_buffer: wgpu::Buffer,
+    atlas: Texture,
+    settings: ShadowSettings,
+    num_shadows: u32,
+}
+
+impl ShadowPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        material_layout: &wgpu::BindGroupLayout,
+        settings: ShadowSettings,
+    ) -> Self {
+        let face_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<Mat4>() as _),
+                },
+                count: None,
+            }],
+            label: Some("shadow_face_bind_group_layout"),
+        });
+
+        let pipelines = DepthPipelines::new(device, "Shadow", &face_layout, material_layout);
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Uniform Buffer"),
+            size: std::mem::size_of::<ShadowUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let face_stride = device
+            .limits()
+            .min_uniform_buffer_offset_alignment
+            .max(std::mem::size_of::<Mat4>() as u32);
+        let (face_buffer, face_bind_group) =
+            Self::create_faces(device, &face_layout, face_stride, &settings);
+        let (atlas, uniform) = Self::create_atlas(device, &settings);
+
+        Self {
+            pipelines,
+            face_layout,
+            face_buffer,
+            face_bind_group,
+            face_stride,
+            uniform,
+            uniform_buffer,
+            atlas,
+            settings,
+            num_shadows: 0,
+        }
+    }
+
+    fn create_faces(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        stride: u32,
+        settings: &ShadowSettings,
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let num_faces = settings.max_shadow_maps.max(1) as u64 * 6;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Face Buffer"),
+            size: num_faces * stride as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<Mat4>() as _),
+                }),
+            }],
+            label: Some("shadow_face_bind_group"),
+        });
+
+        (buffer, bind_group)
+    }
+
+    fn create_atlas(device: &wgpu::Device, settings: &ShadowSettings) -> (Texture, ShadowUniform) {
+        let num_tiles = settings.max_shadow_maps.max(1) * 6;
+        let tiles_per_row = (num_tiles as f32).sqrt().ceil() as u32;
+        let rows = num_tiles.div_ceil(tiles_per_row);
+
+        let max_dimension = device.limits().max_texture_dimension_2d;
+        let mut tile_size = settings.resolution.max(1);
+        if tile_size * tiles_per_row > max_dimension {
+            tile_size = max_dimension / tiles_per_row;
+            log::warn!(
+                "shadow resolution {} does not fit in the device limits, clamping to {}",
+                settings.resolution,
+                tile_size
+            );
+        }
+
+        let (width, height) = (tile_size * tiles_per_row, tile_size * rows);
+        let atlas = Texture::create_depth_texture(device, width, height, 1, "shadow_atlas");
+
+        let uniform = ShadowUniform {
+            depth_bias: settings.depth_bias,
+            near: settings.near,
+            far: settings.far,
+            num_shadows: 0,
+            tiles_per_row,
+            tile_size: tile_size as f32,
+            texel_size: [1.0 / width as f32, 1.0 / height as f32],
+        };
+
+        (atlas, uniform)
+    }
+
+    pub fn set_settings(&mut self, device: &wgpu::Device, settings: ShadowSettings) {
+        if settings.max_shadow_maps != self.settings.max_shadow_maps {
+            (self.face_buffer, self.face_bind_group) =
+                Self::create_faces(device, &self.face_layout, self.face_stride, &settings);
+        }
+        (self.atlas, self.uniform) = Self::create_atlas(device, &settings);
+        self.settings = settings;
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, shadow_lights: &[Vec3], stats: &mut FrameStats) {
+        self.num_shadows = shadow_lights
+            .len()
+            .min(self.settings.max_shadow_maps as usize) as u32;
+
+        let proj = Mat4::perspective_rh(FRAC_PI_2, 1.0, self.settings.near, self.settings.far);
+        for (i, position) in shadow_lights
+            .iter()
+            .take(self.num_shadows as usize)
+            .enumerate()
+        {
+            for (face, (dir, up)) in CUBE_FACES.iter().enumerate() {
+                let view = Mat4::look_at_rh(*position, *position + *dir, *up);
+                let offset = ((i * 6 + face) as u32 * self.face_stride) as wgpu::BufferAddress;
+                stats.write(
+                    queue,
+                    &self.face_buffer,
+                    offset,
+                    bytemuck::cast_slice(&(proj * view).to_cols_array()),
+                );
+            }
+        }
+
+        self.uniform.num_shadows = self.num_shadows;
+        stats.write(
+            queue,
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+
+    pub fn process(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        instance_buffer: &InstanceBuffer,
+        objects: &[(Arc<Model>, Range<usize>, Range<u32>)],
+        stats: &mut FrameStats,
+    ) {
+        if self.num_shadows == 0 {
+            return;
+        }
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("shadow_render_pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.atlas.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        let mut bound = None;
+        let tile_size = self.uniform.tile_size;
+        for tile in 0..self.num_shadows * 6 {
+            let x = (tile % self.uniform.tiles_per_row) as f32 * tile_size;
+            let y = (tile / self.uniform.tiles_per_row) as f32 * tile_size;
+            pass.set_viewport(x, y, tile_size, tile_size, 0.0, 1.0);
+            pass.set_bind_group(0, &self.face_bind_group, &[tile * self.face_stride]);
+            for (model, meshes, range) in objects {
+                for (buffer, range) in instance_buffer.draws(range.clone()) {
+                    pass.set_vertex_buffer(1, buffer.slice(..));
+                    for mesh in &model.meshes[meshes.clone()] {
+                        self.pipelines.draw_mesh(
+                            &mut pass,
+                            model,
+                            mesh,
+                            range.clone(),
+                            &mut bound,
+                            stats,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.atlas.view
+    }
+
+    pub fn sampler(&self) -> &wgpu::Sampler {
+        &self.atlas.sampler
+    }
+
+    pub fn uniform_buffer(&self) -> &wgpu::Buffer {
+        &self.uniform_buffer
+    }
+
+    pub fn settings(&self) -> &ShadowSettings {
+        &self.settings
+    }
+}
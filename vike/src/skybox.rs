@@ -0,0 +1,449 @@
+use std::sync::Arc;
+
+use glam::Vec3;
+use wgpu::util::DeviceExt;
+
+use crate::{hdr::HdrPipeline, stats::FrameStats, texture::Texture};
+
+#[derive(Clone, Debug)]
+pub struct SkyGradient {
+    pub zenith: Vec3,
+    pub horizon: Vec3,
+    pub ground: Vec3,
+    pub exponent: f32,
+}
+
+impl Default for SkyGradient {
+    fn default() -> Self {
+        Self {
+            zenith: Vec3::new(0.15, 0.35, 0.8),
+            horizon: Vec3::new(0.7, 0.8, 0.9),
+            ground: Vec3::new(0.2, 0.18, 0.16),
+            exponent: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub enum Sky {
+    #[default]
+    Color,
+    Gradient(SkyGradient),
+    Cubemap(Arc<Texture>),
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    zenith: [f32; 4],
+    horizon: [f32; 4],
+    ground: [f32; 4],
+    mode: u32,
+    _padding: [u32; 3],
+}
+
+impl From<&Sky> for SkyUniform {
+    fn from(sky: &Sky) -> Self {
+        match sky {
+            Sky::Gradient(gradient) => Self {
+                zenith: gradient.zenith.extend(1.0).into(),
+                horizon: gradient.horizon.extend(1.0).into(),
+                ground: gradient.ground.extend(gradient.exponent).into(),
+                mode: 0,
+                ..Default::default()
+            },
+            Sky::Cubemap(_) => Self {
+                mode: 1,
+                ..Default::default()
+            },
+            Sky::Color => Self::default(),
+        }
+    }
+}
+
+pub struct SkyboxPipeline {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    placeholder: Texture,
+    sky: Sky,
+}
+
+impl SkyboxPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("skybox_bind_group_layout"),
+        });
+
+        let pipeline = Self::create_pipeline(device, camera_layout, &layout, sample_count);
+
+        let placeholder = Texture::create_cubemap(
+            device,
+            1,
+            1,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            Some("skybox_placeholder"),
+        );
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &placeholder.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &[0; 4 * 6],
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4),
+                rows_per_image: Some(1),
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 6,
+            },
+        );
+
+        let sky = Sky::default();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skybox Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[SkyUniform::from(&sky)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = Self::create_bind_group(device, &layout, &placeholder, &uniform_buffer);
+
+        Self {
+            pipeline,
+            layout,
+            uniform_buffer,
+            bind_group,
+            placeholder,
+            sky,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/skybox.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, layout],
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Skybox Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: HdrPipeline::FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        cubemap: &Texture,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&cubemap.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&cubemap.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("skybox_bind_group"),
+        })
+    }
+
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) {
+        self.pipeline = Self::create_pipeline(device, camera_layout, &self.layout, sample_count);
+    }
+
+    pub fn sky(&self) -> &Sky {
+        &self.sky
+    }
+
+    pub fn set_sky(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, sky: Sky) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[SkyUniform::from(&sky)]),
+        );
+        let cubemap = match &sky {
+            Sky::Cubemap(cubemap) => cubemap,
+            _ => &self.placeholder,
+        };
+        self.bind_group =
+            Self::create_bind_group(device, &self.layout, cubemap, &self.uniform_buffer);
+        self.sky = sky;
+    }
+
+    pub fn draw<'a: 'b, 'b>(
+        &'a self,
+        pass: &'b mut wgpu::RenderPass<'a>,
+        camera: &'a wgpu::BindGroup,
+        stats: &mut FrameStats,
+    ) {
+        if let Sky::Color = self.sky {
+            return;
+        }
+
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, camera, &[]);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        stats.pipeline();
+        stats.draw(1);
+    }
+}
+
+pub fn equirect_to_cubemap(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    image: &image::Rgba32FImage,
+    label: Option<&str>,
+) -> Texture {
+    let max_dimension = device.limits().max_texture_dimension_2d;
+    let downsampled;
+    let image = if image.width() > max_dimension || image.height() > max_dimension {
+        let scale = max_dimension as f32 / image.width().max(image.height()) as f32;
+        let width = ((image.width() as f32 * scale) as u32).clamp(1, max_dimension);
+        let height = ((image.height() as f32 * scale) as u32).clamp(1, max_dimension);
+        log::warn!(
+            "{:?} is {}x{}, downsampling to {}x{} to fit the device texture limit",
+            label.unwrap_or("equirect image"),
+            image.width(),
+            image.height(),
+            width,
+            height
+        );
+        downsampled =
+            image::imageops::resize(image, width, height, image::imageops::FilterType::Triangle);
+        &downsampled
+    } else {
+        image
+    };
+    let (width, height) = image.dimensions();
+    let size = (height / 2).next_power_of_two().min(max_dimension).max(1);
+
+    let equirect = Texture::create_2d_texture(
+        device,
+        width,
+        height,
+        wgpu::TextureFormat::Rgba32Float,
+        wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        wgpu::FilterMode::Nearest,
+        Some("equirect_texture"),
+    );
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            aspect: wgpu::TextureAspect::All,
+            texture: &equirect.texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        bytemuck::cast_slice(image.as_raw()),
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(16 * width),
+            rows_per_image: Some(height),
+        },
+        equirect.texture.size(),
+    );
+
+    let cubemap = Texture::create_cubemap(
+        device,
+        size,
+        1,
+        HdrPipeline::FORMAT,
+        wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        label,
+    );
+
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+        label: Some("equirect_bind_group_layout"),
+    });
+
+    let shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/equirect.wgsl"));
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Equirect Pipeline Layout"),
+        bind_group_layouts: &[&layout],
+        push_constant_ranges: &[],
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Equirect Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: HdrPipeline::FORMAT,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Equirect Encoder"),
+    });
+    for face in 0..6u32 {
+        let face_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Equirect Face Buffer"),
+            contents: bytemuck::cast_slice(&[face, 0, 0, 0]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&equirect.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: face_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("equirect_bind_group"),
+        });
+        let view = cubemap.texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_array_layer: face,
+            array_layer_count: Some(1),
+            ..Default::default()
+        });
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("equirect_render_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+    queue.submit(std::iter::once(encoder.finish()));
+
+    cubemap
+}
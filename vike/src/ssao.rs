@@ -0,0 +1,308 @@
+use glam::{Mat4, Vec3};
+
+use crate::{stats::FrameStats, texture::Texture};
+
+const MAX_SAMPLES: usize = 64;
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+#[derive(Clone, Debug)]
+pub struct SsaoSettings {
+    pub radius: f32,
+    pub intensity: f32,
+    pub bias: f32,
+    pub sample_count: u32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            radius: 0.5,
+            intensity: 1.5,
+            bias: 0.025,
+            sample_count: 16,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsaoUniform {
+    proj: [[f32; 4]; 4],
+    inv_proj: [[f32; 4]; 4],
+    radius: f32,
+    intensity: f32,
+    bias: f32,
+    num_samples: u32,
+    kernel: [[f32; 4]; MAX_SAMPLES],
+}
+
+pub struct SsaoPipeline {
+    ssao: wgpu::RenderPipeline,
+    blur: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    raw: Texture,
+    blurred: Texture,
+    ssao_bind_group: wgpu::BindGroup,
+    blur_bind_group: wgpu::BindGroup,
+    kernel: [[f32; 4]; MAX_SAMPLES],
+    settings: SsaoSettings,
+}
+
+impl SsaoPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+        settings: SsaoSettings,
+    ) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0),
+                texture_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("ssao_bind_group_layout"),
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/ssao.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SSAO Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let ssao = create_pipeline("fs_ssao");
+        let blur = create_pipeline("fs_blur");
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("SSAO Uniform Buffer"),
+            size: std::mem::size_of::<SsaoUniform>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let (raw, blurred) = Self::create_textures(device, width, height);
+        let (ssao_bind_group, blur_bind_group) =
+            Self::create_bind_groups(device, &layout, depth_view, &raw, &uniform_buffer);
+
+        Self {
+            ssao,
+            blur,
+            layout,
+            uniform_buffer,
+            raw,
+            blurred,
+            ssao_bind_group,
+            blur_bind_group,
+            kernel: Self::kernel(),
+            settings,
+        }
+    }
+
+    // Hemisphere samples that cluster towards the origin, so nearby geometry
+    // contributes more occlusion than distant geometry
+    fn kernel() -> [[f32; 4]; MAX_SAMPLES] {
+        let mut state = 0x9e3779b9u32;
+        let mut random = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32
+        };
+
+        let mut kernel = [[0.0; 4]; MAX_SAMPLES];
+        for (i, sample) in kernel.iter_mut().enumerate() {
+            let direction = Vec3::new(random() * 2.0 - 1.0, random() * 2.0 - 1.0, random())
+                .normalize_or(Vec3::Z);
+            let t = i as f32 / MAX_SAMPLES as f32;
+            let scale = 0.1 + 0.9 * t * t;
+            *sample = (direction * random() * scale).extend(0.0).into();
+        }
+        kernel
+    }
+
+    fn create_textures(device: &wgpu::Device, width: u32, height: u32) -> (Texture, Texture) {
+        let create = |label| {
+            Texture::create_2d_texture(
+                device,
+                width,
+                height,
+                FORMAT,
+                wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                wgpu::FilterMode::Linear,
+                Some(label),
+            )
+        };
+        (create("ssao_texture"), create("ssao_blurred_texture"))
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        depth_view: &wgpu::TextureView,
+        raw: &Texture,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        let create = |input| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(depth_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(input),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("ssao_bind_group"),
+            })
+        };
+        (create(depth_view), create(&raw.view))
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        (self.raw, self.blurred) = Self::create_textures(device, width, height);
+        (self.ssao_bind_group, self.blur_bind_group) = Self::create_bind_groups(
+            device,
+            &self.layout,
+            depth_view,
+            &self.raw,
+            &self.uniform_buffer,
+        );
+    }
+
+    pub fn settings(&self) -> &SsaoSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: SsaoSettings) {
+        self.settings = settings;
+    }
+
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.blurred.texture
+    }
+
+    fn pass(
+        encoder: &mut wgpu::CommandEncoder,
+        stats: &mut FrameStats,
+        target: &wgpu::TextureView,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("ssao_render_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        stats.pipeline();
+        stats.draw(1);
+    }
+
+    pub fn process(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        proj: Mat4,
+        stats: &mut FrameStats,
+    ) {
+        let uniform = SsaoUniform {
+            proj: proj.to_cols_array_2d(),
+            inv_proj: proj.inverse().to_cols_array_2d(),
+            radius: self.settings.radius,
+            intensity: self.settings.intensity,
+            bias: self.settings.bias,
+            num_samples: self.settings.sample_count.min(MAX_SAMPLES as u32),
+            kernel: self.kernel,
+        };
+        stats.write(
+            queue,
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[uniform]),
+        );
+
+        Self::pass(
+            encoder,
+            stats,
+            &self.raw.view,
+            &self.ssao,
+            &self.ssao_bind_group,
+        );
+        Self::pass(
+            encoder,
+            stats,
+            &self.blurred.view,
+            &self.blur,
+            &self.blur_bind_group,
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use std::fmt;
+use std::sync::mpsc;
+
+use web_time::Duration;
+
+const MAX_TIMESTAMPS: u32 = 32;
+
+#[derive(Clone, Copy, Debug)]
+pub struct PassTiming {
+    pub label: &'static str,
+    pub duration: Duration,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FrameStats {
+    pub frame_time: Duration,
+    pub pre_frame_time: Duration,
+    pub encode_time: Duration,
+    pub draw_calls: u32,
+    pub pipeline_switches: u32,
+    pub instances: u32,
+    pub triangles: u64,
+    pub lights: u32,
+    pub bytes_written: u64,
+    // lags the CPU counters by a frame or two, empty without timestamp queries
+    pub gpu_timings: Vec<PassTiming>,
+}
+
+impl FrameStats {
+    pub fn gpu_time(&self) -> Duration {
+        self.gpu_timings.iter().map(|timing| timing.duration).sum()
+    }
+
+    pub(crate) fn draw(&mut self, triangles: u64) {
+        self.draw_calls += 1;
+        self.triangles += triangles;
+    }
+
+    pub(crate) fn pipeline(&mut self) {
+        self.pipeline_switches += 1;
+    }
+
+    pub(crate) fn write(
+        &mut self,
+        queue: &wgpu::Queue,
+        buffer: &wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        data: &[u8],
+    ) {
+        queue.write_buffer(buffer, offset, data);
+        self.bytes_written += data.len() as u64;
+    }
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        writeln!(
+            f,
+            "frame {:.2} ms (pre_frame {:.2} ms, encode {:.2} ms)",
+            ms(self.frame_time),
+            ms(self.pre_frame_time),
+            ms(self.encode_time),
+        )?;
+        writeln!(
+            f,
+            "{} draw calls, {} pipeline switches, {} instances, {} triangles",
+            self.draw_calls, self.pipeline_switches, self.instances, self.triangles,
+        )?;
+        write!(
+            f,
+            "{} lights, {} bytes written",
+            self.lights, self.bytes_written
+        )?;
+        if !self.gpu_timings.is_empty() {
+            write!(f, "\ngpu {:.2} ms", ms(self.gpu_time()))?;
+            for timing in &self.gpu_timings {
+                write!(f, "\n  {} {:.3} ms", timing.label, ms(timing.duration))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Timestamps written between passes on the command encoder. Results are read
+// back without stalling, so frames are skipped while a readback is in flight
+pub(crate) struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    period: f32,
+    labels: Vec<&'static str>,
+    active: bool,
+    pending: Option<(Vec<&'static str>, mpsc::Receiver<bool>)>,
+    timings: Vec<PassTiming>,
+}
+
+impl GpuTimer {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("GPU Timer Queries"),
+            ty: wgpu::QueryType::Timestamp,
+            count: MAX_TIMESTAMPS,
+        });
+        let size = MAX_TIMESTAMPS as u64 * wgpu::QUERY_SIZE as u64;
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Timer Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Timer Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            labels: Vec::new(),
+            active: false,
+            pending: None,
+            timings: Vec::new(),
+        })
+    }
+
+    pub fn timings(&self) -> &[PassTiming] {
+        &self.timings
+    }
+
+    pub fn begin(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        self.collect(device);
+        self.labels.clear();
+        self.active = self.pending.is_none();
+        if self.active {
+            encoder.write_timestamp(&self.query_set, 0);
+        }
+    }
+
+    // Ends the span started by the previous mark (or begin)
+    pub fn mark(&mut self, encoder: &mut wgpu::CommandEncoder, label: &'static str) {
+        let index = self.labels.len() as u32 + 1;
+        if !self.active || index >= MAX_TIMESTAMPS {
+            return;
+        }
+        encoder.write_timestamp(&self.query_set, index);
+        self.labels.push(label);
+    }
+
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if !self.active || self.labels.is_empty() {
+            self.active = false;
+            return;
+        }
+        let count = self.labels.len() as u32 + 1;
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            count as u64 * wgpu::QUERY_SIZE as u64,
+        );
+    }
+
+    // Must be called after the encoder passed to resolve has been submitted
+    pub fn map(&mut self) {
+        if !self.active {
+            return;
+        }
+        self.active = false;
+
+        let (sender, receiver) = mpsc::channel();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result.is_ok());
+            });
+        self.pending = Some((std::mem::take(&mut self.labels), receiver));
+    }
+
+    fn collect(&mut self, device: &wgpu::Device) {
+        let Some((labels, receiver)) = &self.pending else {
+            return;
+        };
+        device.poll(wgpu::Maintain::Poll);
+        let mapped = match receiver.try_recv() {
+            Ok(mapped) => mapped,
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => false,
+        };
+
+        if mapped {
+            {
+                let data = self.readback_buffer.slice(..).get_mapped_range();
+                let timestamps: &[u64] = bytemuck::cast_slice(&data);
+                self.timings = labels
+                    .iter()
+                    .zip(timestamps.windows(2))
+                    .map(|(&label, span)| PassTiming {
+                        label,
+                        duration: Duration::from_nanos(
+                            (span[1].saturating_sub(span[0]) as f64 * self.period as f64) as u64,
+                        ),
+                    })
+                    .collect();
+            }
+            self.readback_buffer.unmap();
+        }
+        self.pending = None;
+    }
+}